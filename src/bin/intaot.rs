@@ -0,0 +1,31 @@
+//! An ahead-of-time compiler from intcode programs to Rust modules.
+//!
+//! Run it with the path to a program, e.g. `cargo run --bin intaot --
+//! input/2019/day9.txt > src/day9_boost.rs`. The module refers to the
+//! interpreter through `crate::intcode` unless another path is given, e.g.
+//! `aoc2019::intcode` for a module outside of this crate.
+extern crate aoc2019;
+
+use aoc2019::intcode::{self, aot};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: intaot <program> [intcode module path]");
+            std::process::exit(2);
+        }
+    };
+    let intcode = args.next().unwrap_or_else(|| "crate::intcode".to_owned());
+
+    let program = match intcode::load_program(&path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    print!("{}", aot::transpile(&program, &intcode));
+}
@@ -0,0 +1,28 @@
+//! Recovers the control-flow graph of an intcode program.
+//!
+//! Run it with the path to a program, e.g. `cargo run --bin intcfg --
+//! input/2019/day9.txt | dot -Tsvg > day9.svg`, to get the graph in the DOT
+//! language of Graphviz.
+extern crate aoc2019;
+
+use aoc2019::intcode::{self, cfg::Cfg};
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: intcfg <program>");
+            std::process::exit(2);
+        }
+    };
+
+    let program = match intcode::load_program(&path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    print!("{}", Cfg::new(&program).to_dot());
+}
@@ -0,0 +1,347 @@
+//! An interactive debugger for intcode programs.
+//!
+//! Run it with the path to a program, e.g. `cargo run --bin intdbg --
+//! input/2019/day11.txt`, and type `help` for a list of commands.
+extern crate aoc2019;
+
+use aoc2019::intcode::memory::Memory as _;
+use aoc2019::intcode::profile::Profiler;
+use aoc2019::intcode::{self, disasm, IoBus, Machine, StopReason, WatchKind, WatchRange};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, BufRead as _, Write as _};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint, a halt, or missing input
+  p, profile           run until a halt or missing input, then print a profile
+  b, break <pc>        set a breakpoint
+  d, delete <pc>       delete a breakpoint
+  bl                   list all breakpoints
+  w, watch <r|w|a> <addr> [n]
+                       stop once n cells from addr (default 1) are read,
+                       written, or accessed at all; addr may be rb+<offset>
+                       to watch cells relative to the relative base
+  wd <n>               delete the nth watchpoint
+  wl                   list all watchpoints
+  i, instr             print the instruction at pc
+  l, list [addr] [n]   disassemble n lines from addr (default pc, 8 lines)
+  r, regs              print pc and the relative base
+  x <addr> [n]         dump n cells from addr (default 1)
+  set <addr> <value>   write a value into memory
+  in <values...>       queue input values
+  out                  print all output so far
+  q, quit              exit the debugger";
+
+/// Input is fed by hand, and output is collected to be shown as it happens.
+#[derive(Default)]
+struct DebugBus {
+    input: VecDeque<isize>,
+    output: Vec<isize>,
+}
+
+impl IoBus for DebugBus {
+    fn input(&mut self) -> Option<isize> {
+        self.input.pop_front()
+    }
+
+    fn output(&mut self, i: isize) -> bool {
+        println!("output: {}", i);
+        self.output.push(i);
+        false
+    }
+}
+
+struct Debugger {
+    machine: Machine,
+    bus: DebugBus,
+    breakpoints: BTreeSet<usize>,
+    stopped: Option<StopReason>,
+}
+
+impl Debugger {
+    /// Execute a single instruction, reporting it if it stops the machine.
+    ///
+    /// Returns whether the machine may continue.
+    fn step(&mut self) -> bool {
+        if let Some(StopReason::Halted) | Some(StopReason::Error(_)) = self.stopped {
+            println!("the machine has stopped: {:?}", self.stopped.unwrap());
+            return false;
+        }
+
+        self.stopped = match self.machine.step(&mut self.bus) {
+            Ok(stop) => stop,
+            Err(e) => Some(StopReason::Error(e)),
+        };
+        match self.stopped {
+            None | Some(StopReason::OutputPaused) | Some(StopReason::BudgetExhausted) => true,
+            Some(StopReason::NeedsInput) => {
+                println!("waiting for input; queue some with `in <values...>`");
+                false
+            }
+            Some(StopReason::Halted) => {
+                println!("halted");
+                false
+            }
+            Some(StopReason::Watchpoint {
+                pc,
+                address,
+                old,
+                new,
+            }) => {
+                println!(
+                    "watchpoint hit at {:04}: [{}] was {}, is {}",
+                    pc, address, old, new
+                );
+                false
+            }
+            Some(StopReason::Error(e)) => {
+                println!("error: {}", e);
+                false
+            }
+        }
+    }
+
+    fn print_instr(&self) {
+        match self.machine.instr() {
+            Ok(instr) => println!("{:04}: {}", self.machine.pc(), instr),
+            Err(e) => println!("{:04}: <{}>", self.machine.pc(), e),
+        }
+    }
+
+    fn list(&self, mut address: usize, lines: usize) {
+        let memory = self.machine.memory();
+        for _ in 0..lines {
+            let line = match disasm::decode(memory, address) {
+                Some(line) => line,
+                None => break,
+            };
+            let marker = if address == self.machine.pc() {
+                '>'
+            } else if self.breakpoints.contains(&address) {
+                '*'
+            } else {
+                ' '
+            };
+            println!("{}{:04}: {}", marker, address, line);
+            address += line.size();
+        }
+    }
+
+    /// Set a watchpoint from the arguments of the `watch` command.
+    fn watch(&mut self, args: &[&str]) -> Result<(), String> {
+        let kind = match args.first() {
+            Some(&"r") => WatchKind::Read,
+            Some(&"w") => WatchKind::Write,
+            Some(&"a") => WatchKind::Access,
+            _ => return Err("`watch` needs a kind: r, w, or a".to_owned()),
+        };
+        let from = args.get(1).ok_or("`watch` needs an address")?;
+        let count = match args.get(2) {
+            Some(count) => parse(count)?.max(0),
+            None => 1,
+        };
+        match from.strip_prefix("rb+") {
+            Some(offset) => {
+                let offset = parse(offset)?;
+                self.machine
+                    .watch_relative(kind, offset..offset.saturating_add(count));
+            }
+            None => match parse(from)? {
+                from if from >= 0 => {
+                    let from = from as usize;
+                    self.machine
+                        .watch(kind, from..from.saturating_add(count as usize));
+                }
+                from => return Err(format!("invalid address: {}", from)),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Handle a single command. Returns `false` when the debugger should exit.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        if let "w" | "watch" = command {
+            self.watch(&words.collect::<Vec<_>>())?;
+            return Ok(true);
+        }
+        let args = words.map(parse).collect::<Result<Vec<isize>, _>>()?;
+        let address = |idx: usize| -> Result<usize, String> {
+            match args.get(idx) {
+                Some(&a) if a >= 0 => Ok(a as usize),
+                Some(a) => Err(format!("invalid address: {}", a)),
+                None => Err(format!("`{}` needs an address", command)),
+            }
+        };
+
+        match command {
+            "s" | "step" => {
+                let count = args.first().copied().unwrap_or(1);
+                for _ in 0..count {
+                    if !self.step() {
+                        break;
+                    }
+                }
+                self.print_instr();
+            }
+            "c" | "continue" => {
+                while self.step() {
+                    if self.breakpoints.contains(&self.machine.pc()) {
+                        println!("breakpoint hit");
+                        break;
+                    }
+                }
+                self.print_instr();
+            }
+            "p" | "profile" => {
+                if let Some(StopReason::Halted) | Some(StopReason::Error(_)) = self.stopped {
+                    println!("the machine has stopped: {:?}", self.stopped.unwrap());
+                    return Ok(true);
+                }
+
+                let mut profiler = Profiler::new(self.machine.memory());
+                let res = self.machine.run_traced(&mut self.bus, &mut profiler);
+                self.stopped = Some(res.stop);
+                print!("{}", profiler);
+                self.print_instr();
+            }
+            "b" | "break" => {
+                self.breakpoints.insert(address(0)?);
+            }
+            "d" | "delete" => {
+                if !self.breakpoints.remove(&address(0)?) {
+                    return Err("no such breakpoint".to_owned());
+                }
+            }
+            "bl" => {
+                for pc in &self.breakpoints {
+                    println!("{:04}", pc);
+                }
+            }
+            "wd" => {
+                let n = address(0)?;
+                let id = self.machine.watchpoints().nth(n).map(|(id, _)| id);
+                match id {
+                    Some(id) => self.machine.unwatch(id),
+                    None => return Err("no such watchpoint".to_owned()),
+                };
+            }
+            "wl" => {
+                for (n, (_, watchpoint)) in self.machine.watchpoints().enumerate() {
+                    let kind = match watchpoint.kind {
+                        WatchKind::Read => "read",
+                        WatchKind::Write => "write",
+                        WatchKind::Access => "access",
+                    };
+                    match &watchpoint.range {
+                        WatchRange::Absolute(range) => {
+                            println!("{}: {} {}..{}", n, kind, range.start, range.end)
+                        }
+                        WatchRange::Relative(range) => {
+                            println!("{}: {} rb+{}..rb+{}", n, kind, range.start, range.end)
+                        }
+                    }
+                }
+            }
+            "i" | "instr" => self.print_instr(),
+            "l" | "list" => {
+                let from = match args.first() {
+                    Some(_) => address(0)?,
+                    None => self.machine.pc(),
+                };
+                let lines = args.get(1).copied().unwrap_or(8).max(0) as usize;
+                self.list(from, lines);
+            }
+            "r" | "regs" => println!(
+                "pc: {}, relative base: {}",
+                self.machine.pc(),
+                self.machine.relative_base()
+            ),
+            "x" => {
+                let from = address(0)?;
+                let count = args.get(1).copied().unwrap_or(1).max(0) as usize;
+                let memory = self.machine.memory();
+                for address in from..from + count {
+                    println!(
+                        "{:04}: {}",
+                        address,
+                        memory.get(address).copied().unwrap_or(0)
+                    );
+                }
+            }
+            "set" => {
+                let at = address(0)?;
+                let value = *args.get(1).ok_or("`set` needs a value")?;
+                let limit = self.machine.memory_limit();
+                if !self.machine.memory_mut().poke(at, value, limit) {
+                    return Err(format!("address {} exceeds the memory limit", at));
+                }
+            }
+            "in" => {
+                self.bus.input.extend(args);
+                if self.stopped == Some(StopReason::NeedsInput) {
+                    self.stopped = None;
+                }
+            }
+            "out" => println!("{:?}", self.bus.output),
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("unknown command `{}`; try `help`", command)),
+        }
+
+        Ok(true)
+    }
+}
+
+fn parse(word: &str) -> Result<isize, String> {
+    word.parse().map_err(|_| format!("not a number: {}", word))
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: intdbg <program>");
+            std::process::exit(2);
+        }
+    };
+    let program = match intcode::load_program(&path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut debugger = Debugger {
+        machine: Machine::new(program),
+        bus: DebugBus::default(),
+        breakpoints: BTreeSet::new(),
+        stopped: None,
+    };
+    debugger.print_instr();
+
+    let stdin = io::stdin();
+    loop {
+        print!("(intdbg) ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        match debugger.command(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -7,8 +7,9 @@ const GOAL: isize = 19690720;
 fn attempt(mut input: Vec<isize>, noun: isize, verb: isize) -> isize {
     input[1] = noun;
     input[2] = verb;
-    let _ = run(&mut input, (0, 0), &mut NoIoBusImpl::default());
-    input[0]
+    let mut machine = Machine::new(input);
+    let _ = machine.run(&mut NoIoBusImpl::default());
+    machine.memory()[0]
 }
 
 #[aoc_generator(day2)]
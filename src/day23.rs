@@ -0,0 +1,31 @@
+use crate::intcode::network::{Event, Network, NAT};
+
+#[aoc_generator(day23)]
+pub fn gen(input: &str) -> Vec<isize> {
+    input.split(',').flat_map(str::parse).collect()
+}
+
+#[aoc(day23, part1)]
+pub fn part1_impl1(program: &[isize]) -> isize {
+    let mut network = Network::new(program.to_vec(), 50);
+    loop {
+        match network.next_event().expect("network failed") {
+            Event::Sent { packet, .. } if packet.dest == NAT => return packet.y,
+            _ => {}
+        }
+    }
+}
+
+#[aoc(day23, part2)]
+pub fn part2_impl1(program: &[isize]) -> isize {
+    let mut network = Network::new(program.to_vec(), 50);
+    let mut last = None;
+    loop {
+        if let Event::Woken(packet) = network.next_event().expect("network failed") {
+            if last == Some(packet.y) {
+                return packet.y;
+            }
+            last = Some(packet.y);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::intcode::threaded::{run_threaded, MpscBus};
+use crate::intcode::topology::Topology;
 use crate::intcode::*;
 use itertools::Itertools as _;
 
@@ -6,93 +8,87 @@ pub fn gen(input: &str) -> Vec<isize> {
     input.split(',').flat_map(str::parse).collect()
 }
 
-fn calculate_thrust(
-    program: &mut Vec<isize>,
-    pc: usize,
-    phase: Option<isize>,
-    input: isize,
-) -> (isize, RunResult) {
-    struct PhaseIoBus {
-        phase: Option<isize>,
-        input_value: isize,
-        output: isize,
-    };
-    impl IoBus for PhaseIoBus {
-        fn input(&mut self) -> Option<isize> {
-            if let Some(phase) = self.phase {
-                self.phase = None;
-                return Some(phase);
-            }
-
-            Some(self.input_value)
-        }
-
-        fn output(&mut self, i: isize) -> bool {
-            self.output = i;
-            true
-        }
+/// Run the amplifiers with the given phase settings in a chain, or in a ring
+/// with a feedback loop, and return the final output of the last one.
+fn thrust(program: &[isize], config: &[isize], feedback: bool) -> isize {
+    let mut topology = Topology::new();
+    let amplifiers: Vec<_> = config
+        .iter()
+        .enumerate()
+        .map(|(idx, &phase)| {
+            let inputs = if idx == 0 {
+                vec![phase, 0]
+            } else {
+                vec![phase]
+            };
+            topology.node(Machine::new(program.to_vec()), inputs)
+        })
+        .collect();
+    for pair in amplifiers.windows(2) {
+        topology.edge(pair[0], pair[1]);
+    }
+    let last = amplifiers[amplifiers.len() - 1];
+    if feedback {
+        topology.edge(last, amplifiers[0]);
     }
 
-    let mut bus = PhaseIoBus {
-        phase,
-        input_value: input,
-        output: 0,
-    };
-    let result = run(program, (pc, 0), &mut bus);
-    (bus.output, result)
+    let report = topology.run();
+    *report[last].outputs.last().expect("no thrust")
 }
 
 #[aoc(day7, part1)]
 pub fn part1_impl1(program: &Vec<isize>) -> isize {
-    let mut max = 0;
-    for config in (0..5).permutations(5) {
-        let mut result = 0;
-        for phase in config {
-            result = calculate_thrust(&mut program.clone(), 0, Some(phase), result).0;
-        }
-        max = std::cmp::max(result, max);
-    }
-
-    max
+    (0..5)
+        .permutations(5)
+        .map(|config| thrust(program, &config, false))
+        .max()
+        .unwrap()
 }
 
 #[aoc(day7, part2)]
 pub fn part2_impl1(program: &Vec<isize>) -> isize {
-    let mut max = 0;
-    for config in (5..10).permutations(5) {
-        let mut programs = [
-            // Sadly Vec is not Copy, so we can't just do [program.clone(); 5]
-            program.clone(),
-            program.clone(),
-            program.clone(),
-            program.clone(),
-            program.clone(),
-        ];
-        let mut pc = [0; 5];
-        let mut thrust = 0;
+    (5..10)
+        .permutations(5)
+        .map(|config| thrust(program, &config, true))
+        .max()
+        .unwrap()
+}
 
-        for (idx, &phase) in config.iter().enumerate() {
-            let res = calculate_thrust(&mut programs[idx], 0, Some(phase), thrust);
-            thrust = res.0;
-            pc[idx] = res.1.pc;
-        }
+#[aoc(day7, part2, threads)]
+pub fn part2_impl2(program: &Vec<isize>) -> isize {
+    use std::sync::mpsc::channel;
 
-        'forever: loop {
-            for idx in 0..5 {
-                let res = calculate_thrust(&mut programs[idx], pc[idx], None, thrust);
-                thrust = res.0;
-                pc[idx] = res.1.pc;
-                // Not using stopped() is intentional
-                if res.1.has_halted {
-                    break 'forever;
-                }
+    (5..10)
+        .permutations(5)
+        .map(|config| {
+            let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| channel()).unzip();
+            for (sender, &phase) in senders.iter().zip(config.iter()) {
+                sender.send(phase).unwrap();
             }
+            senders[0].send(0).unwrap();
 
-            max = std::cmp::max(thrust, max);
-        }
-    }
+            // Every amplifier feeds the next one, and the last one feeds the
+            // first.
+            let amplifiers: Vec<_> = receivers
+                .into_iter()
+                .enumerate()
+                .map(|(idx, input)| {
+                    let bus = MpscBus {
+                        input,
+                        output: senders[(idx + 1) % senders.len()].clone(),
+                    };
+                    (Machine::new(program.clone()), bus)
+                })
+                .collect();
+            drop(senders);
+            let results = run_threaded(amplifiers);
 
-    max
+            // The first amplifier has halted before the last one's final
+            // output.
+            results[0].1.try_recv().expect("no thrust")
+        })
+        .max()
+        .unwrap()
 }
 
 #[test]
@@ -125,6 +121,13 @@ fn test_examples() {
         ]),
         139629729,
     );
+    assert_eq!(
+        part2_impl2(&vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ]),
+        139629729,
+    );
     assert_eq!(
         part2_impl1(&vec![
             3, 52, 1001, 52, -5, 52, 3, 53, 1, 52, 56, 54, 1007, 54, 5, 55, 1005, 55, 26, 1001, 54,
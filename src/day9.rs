@@ -1,29 +1,29 @@
 use crate::intcode::*;
 
 #[aoc_generator(day9)]
-pub fn gen(input: &str) -> Vec<isize> {
+pub fn gen(input: &str) -> Vec<i64> {
     input.split(',').flat_map(str::parse).collect()
 }
 
 #[aoc(day9, part1)]
-pub fn part1_impl1(program: &Vec<isize>) -> isize {
+pub fn part1_impl1(program: &Vec<i64>) -> i64 {
     keycode(program.clone(), 1)
 }
 
 #[aoc(day9, part2)]
-pub fn part2_impl1(program: &Vec<isize>) -> isize {
+pub fn part2_impl1(program: &Vec<i64>) -> i64 {
     keycode(program.clone(), 2)
 }
 
-fn keycode(mut program: Vec<isize>, input: isize) -> isize {
-    struct BoostIoBus(isize);
+fn keycode(mut program: Vec<i64>, input: i64) -> i64 {
+    struct BoostIoBus(i64);
 
-    impl IoBus for BoostIoBus {
-        fn input(&mut self) -> Option<isize> {
+    impl IoBus<i64> for BoostIoBus {
+        fn input(&mut self) -> Option<i64> {
             Some(self.0)
         }
 
-        fn output(&mut self, i: isize) -> bool {
+        fn output(&mut self, i: i64) -> bool {
             self.0 = i;
             true
         }
@@ -46,150 +46,1090 @@
 //! `A` represents the 3rd parameter, `B` the 2nd, and `C` the 1st. The
 //! different modes are represented by different values: `0` is `Position`,
 //! `1` is `Immediate`, and `2` is `Relative`.
+use self::cache::DecodeCache;
+use self::cell::Cell;
+use self::memory::{Memory, DEFAULT_MEMORY_LIMIT};
 use crate::DigitAtPosition as _;
-
-#[derive(Debug, Clone, Copy, Default)]
+use std::ops::Range;
+
+pub mod aot;
+pub mod ascii;
+pub mod asm;
+mod cache;
+pub mod cell;
+pub mod cfg;
+pub mod coverage;
+pub mod disasm;
+pub mod executor;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod selfmod;
+pub mod snapshot;
+pub mod threaded;
+pub mod topology;
+pub mod trace;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct RunResult {
     pub pc: usize,
     pub relative_base: usize,
-    pub programmatic_break: bool,
-    pub has_halted: bool,
+    pub stop: StopReason,
+
+    /// The amount of instructions executed during this run.
+    pub instructions: u64,
+
+    /// The cost of the instructions executed during this run, according to
+    /// the [`CostModel`] of the machine.
+    pub cost: u64,
 }
 
 impl RunResult {
-    pub fn stopped(&self) -> bool {
-        self.has_halted || self.programmatic_break
+    #[inline(always)]
+    pub fn has_halted(&self) -> bool {
+        self.stop == StopReason::Halted
     }
 }
 
-#[inline(always)]
-pub fn run(
-    program: &mut Vec<isize>,
-    (pc, relative_base): (usize, usize),
-    io_handler: &mut impl IoBus,
-) -> RunResult {
-    let mut result = RunResult {
-        pc,
-        relative_base,
-        programmatic_break: false,
-        has_halted: false,
-    };
+/// Why a machine stopped running.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StopReason {
+    /// A `Hlt` instruction was executed. The machine will not do anything more.
+    Halted,
 
-    while !result.has_halted {
-        debug_assert!(result.pc < program.len());
-        let mut inc = true;
-        let instr = Instr::parse(&program[result.pc..]);
-        match instr {
-            Instr::Hlt => {
-                result.has_halted = true;
-                result.pc += instr.size();
-                return result;
+    /// The I/O bus had no input for an `Input` instruction.
+    NeedsInput,
+
+    /// The I/O bus asked the machine to pause after an `Output` instruction.
+    OutputPaused,
+
+    /// The budget of the machine doesn't cover the next instruction, which
+    /// is executed once the machine is resumed with enough budget.
+    BudgetExhausted,
+
+    /// The program did something invalid.
+    ///
+    /// Only returned by [`Machine::run`]; [`Machine::try_run`] returns the
+    /// error itself instead.
+    Error(IntcodeError),
+
+    /// The instruction at `pc` accessed the `address`, which a
+    /// [watchpoint](Machine::watch) covers.
+    ///
+    /// The instruction has been executed in full, so the machine resumes with
+    /// the next one. A write changed the cell from `old` to `new`, while a
+    /// read leaves both at the value read. Writes are reported before reads.
+    /// Values of cells which don't fit an `isize` are saturated.
+    ///
+    /// An `Output` which also paused the machine stops it with
+    /// [`Self::OutputPaused`] first; the watchpoint is reported once the
+    /// machine is resumed, before it executes anything more.
+    Watchpoint {
+        pc: usize,
+        address: usize,
+        old: isize,
+        new: isize,
+    },
+}
+
+/// The accesses a watchpoint stops the machine on.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+
+    /// Both reads and writes.
+    Access,
+}
+
+impl WatchKind {
+    #[inline(always)]
+    fn covers(self, kind: WatchKind) -> bool {
+        self == WatchKind::Access || self == kind
+    }
+}
+
+/// The cells a watchpoint covers.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum WatchRange {
+    Absolute(Range<usize>),
+
+    /// Offsets from the relative base the instruction accessing the cell was
+    /// executed with.
+    Relative(Range<isize>),
+}
+
+impl WatchRange {
+    #[inline(always)]
+    fn contains(&self, address: usize, relative_base: usize) -> bool {
+        match self {
+            Self::Absolute(range) => range.contains(&address),
+            Self::Relative(range) => {
+                range.contains(&(address as isize).wrapping_sub(relative_base as isize))
             }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub range: WatchRange,
+}
+
+/// A watchpoint set on a [`Machine`], to remove it again.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct WatchId(usize);
+
+/// How much executing an instruction costs, to be taken from the budget of a
+/// machine.
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub enum CostModel {
+    /// Every instruction costs `1`, so the budget counts instructions.
+    #[default]
+    Instructions,
+
+    /// Every instruction costs the weight of its op code: `weights[n]` is the
+    /// cost of op code `n`, and `weights[0]` is the cost of `Hlt`.
+    Weighted([u64; 10]),
+}
+
+impl CostModel {
+    #[inline(always)]
+    pub fn cost(&self, instr: Instr) -> u64 {
+        match self {
+            Self::Instructions => 1,
+            Self::Weighted(weights) => weights[instr.opcode() as usize % 100 % 99],
+        }
+    }
+}
+
+/// What happens when `Add` or `Mul` overflow a cell.
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub enum Overflow {
+    /// Stop the machine with [`IntcodeError::Overflow`].
+    #[default]
+    Trap,
+
+    /// Wrap around at the bounds of the cell, like two's complement does.
+    Wrap,
+
+    /// Clamp the result to the bounds of the cell.
+    Saturate,
+}
+
+impl Overflow {
+    #[inline(always)]
+    fn add<C: Cell>(self, lhs: &C, rhs: &C) -> Option<C> {
+        match self {
+            Self::Trap => lhs.checked_add(rhs),
+            Self::Wrap => Some(lhs.wrapping_add(rhs)),
+            Self::Saturate => Some(lhs.saturating_add(rhs)),
+        }
+    }
+
+    #[inline(always)]
+    fn mul<C: Cell>(self, lhs: &C, rhs: &C) -> Option<C> {
+        match self {
+            Self::Trap => lhs.checked_mul(rhs),
+            Self::Wrap => Some(lhs.wrapping_mul(rhs)),
+            Self::Saturate => Some(lhs.saturating_mul(rhs)),
+        }
+    }
+}
+
+/// An intcode computer.
+///
+/// The machine owns the memory of the program along with its registers, so it
+/// can be stopped by the I/O bus and resumed later on without the caller
+/// having to keep track of where it left off.
+///
+/// The memory is kept in a `Vec` of `isize` cells unless another [`Memory`]
+/// backend is given, which also decides the type of [`Cell`] the machine
+/// computes with.
+///
+/// Every instruction is decoded only once, until a write hits one of its
+/// cells. Changing the memory through [`Self::memory_mut`] forgets every
+/// decoded instruction.
+///
+/// Watchpoints stop the machine whenever the program accesses the cells they
+/// cover; see [`Self::watch`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Machine<M = Vec<isize>> {
+    memory: M,
+    memory_limit: usize,
+    pc: usize,
+    relative_base: usize,
+    last_stop: Option<StopReason>,
+    budget: Option<u64>,
+    cost_model: CostModel,
+    overflow: Overflow,
+    instructions: u64,
+    cost: u64,
+    cache: DecodeCache,
+
+    /// Every watchpoint by its id, leaving `None` where one was removed.
+    watchpoints: Vec<Option<Watchpoint>>,
+
+    /// A watchpoint hit by an instruction which paused the machine already.
+    pending_hit: Option<StopReason>,
+}
+
+impl Machine {
+    /// Create a new machine which starts executing at the first cell of the
+    /// program.
+    pub fn new(program: Vec<isize>) -> Self {
+        Machine::with_memory(program)
+    }
+}
+
+impl<M: Memory + Default> Default for Machine<M> {
+    fn default() -> Self {
+        Machine::with_memory(M::default())
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    /// Create a new machine with the program already in its memory, which
+    /// starts executing at address `0`.
+    pub fn with_memory(memory: M) -> Self {
+        Machine {
+            memory,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            pc: 0,
+            relative_base: 0,
+            last_stop: None,
+            budget: None,
+            cost_model: CostModel::default(),
+            overflow: Overflow::default(),
+            instructions: 0,
+            cost: 0,
+            cache: DecodeCache::default(),
+            watchpoints: Vec::new(),
+            pending_hit: None,
+        }
+    }
+
+    /// The amount of cells the memory may hold; see [`Memory::poke`].
+    #[inline(always)]
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    /// Set the amount of cells the memory may hold. A write which would take
+    /// the memory past the limit stops the machine with
+    /// [`IntcodeError::MemoryLimit`].
+    ///
+    /// This defaults to [`DEFAULT_MEMORY_LIMIT`].
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit;
+    }
+
+    /// The budget left for executing instructions, or `None` if the machine
+    /// may run for as long as it likes.
+    #[inline(always)]
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Set the budget for executing instructions. Once the next instruction
+    /// costs more than is left, the machine stops with
+    /// [`StopReason::BudgetExhausted`].
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    /// Add to the budget left, e.g. to resume a machine which ran out of it.
+    ///
+    /// This does nothing for a machine without a budget.
+    pub fn grant_budget(&mut self, amount: u64) {
+        if let Some(budget) = &mut self.budget {
+            *budget = budget.saturating_add(amount);
+        }
+    }
+
+    #[inline(always)]
+    pub fn cost_model(&self) -> CostModel {
+        self.cost_model
+    }
+
+    /// Set how much instructions cost. This defaults to
+    /// [`CostModel::Instructions`].
+    pub fn set_cost_model(&mut self, cost_model: CostModel) {
+        self.cost_model = cost_model;
+    }
+
+    #[inline(always)]
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Set what happens when `Add` or `Mul` overflow a cell. This defaults to
+    /// [`Overflow::Trap`], whatever the build profile.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Stop the machine with [`StopReason::Watchpoint`] whenever an
+    /// instruction accesses a cell in the range as the kind says.
+    ///
+    /// Only the parameters of instructions are watched; neither decoding the
+    /// instructions themselves nor changes through [`Self::memory_mut`] stop
+    /// the machine.
+    pub fn watch(&mut self, kind: WatchKind, range: Range<usize>) -> WatchId {
+        self.add_watchpoint(Watchpoint {
+            kind,
+            range: WatchRange::Absolute(range),
+        })
+    }
+
+    /// Watch the cells like [`Self::watch`], but with the range as offsets
+    /// from the relative base at the time of every access.
+    pub fn watch_relative(&mut self, kind: WatchKind, range: Range<isize>) -> WatchId {
+        self.add_watchpoint(Watchpoint {
+            kind,
+            range: WatchRange::Relative(range),
+        })
+    }
+
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchId {
+        self.watchpoints.push(Some(watchpoint));
+        WatchId(self.watchpoints.len() - 1)
+    }
+
+    /// Remove a watchpoint. Returns whether it was still set.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let removed = match self.watchpoints.get_mut(id.0) {
+            Some(watchpoint) => watchpoint.take().is_some(),
+            None => false,
+        };
+        // Keep the list empty once every watchpoint is gone, so running the
+        // machine doesn't check for any.
+        while let Some(None) = self.watchpoints.last() {
+            self.watchpoints.pop();
+        }
+        removed
+    }
+
+    /// Every watchpoint which is set, by its id.
+    pub fn watchpoints(&self) -> impl Iterator<Item = (WatchId, &Watchpoint)> {
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, watchpoint)| Some((WatchId(id), watchpoint.as_ref()?)))
+    }
+
+    /// The amount of instructions executed over the life of the machine.
+    #[inline(always)]
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The cost of all instructions executed over the life of the machine.
+    #[inline(always)]
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    #[inline(always)]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    #[inline(always)]
+    pub fn relative_base(&self) -> usize {
+        self.relative_base
+    }
+
+    #[inline(always)]
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    #[inline(always)]
+    pub fn memory_mut(&mut self) -> &mut M {
+        self.cache.clear();
+        &mut self.memory
+    }
+
+    pub fn into_memory(self) -> M {
+        self.memory
+    }
+
+    /// Why the machine stopped the last time it ran, if it has been run yet.
+    ///
+    /// This is `None` after a [`Self::step`] which didn't stop the machine.
+    #[inline(always)]
+    pub fn last_stop(&self) -> Option<StopReason> {
+        self.last_stop
+    }
+
+    /// Decode the instruction the machine will execute next.
+    pub fn instr(&self) -> Result<Instr, IntcodeError> {
+        Instr::decode(&self.memory, self.pc)
+    }
+
+    /// Run the machine until it halts, the I/O bus asks it to stop, or the
+    /// program does something invalid.
+    ///
+    /// Calling this again after the I/O bus stopped the machine continues where
+    /// it left off: right after an `Output` which paused the machine, or at the
+    /// `Input` which had no value, so the input is asked for again. On an
+    /// error, the registers are left pointing at the faulting instruction.
+    pub fn run(&mut self, io_handler: &mut impl IoBus<M::Cell>) -> RunResult {
+        self.run_traced(io_handler, &mut NoTracer)
+    }
+
+    /// Run the machine like [`Self::run`], but return any error separately.
+    pub fn try_run(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+    ) -> Result<RunResult, IntcodeError> {
+        self.try_run_traced(io_handler, &mut NoTracer)
+    }
+
+    /// Run the machine like [`Self::run`], passing every executed instruction
+    /// to the tracer.
+    pub fn run_traced(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+        tracer: &mut impl Tracer<M::Cell>,
+    ) -> RunResult {
+        let start = (self.instructions, self.cost);
+        let stop = self
+            .execute(io_handler, tracer)
+            .unwrap_or_else(StopReason::Error);
+        self.result(stop, start)
+    }
+
+    /// Run the machine like [`Self::try_run`], passing every executed
+    /// instruction to the tracer.
+    pub fn try_run_traced(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+        tracer: &mut impl Tracer<M::Cell>,
+    ) -> Result<RunResult, IntcodeError> {
+        let start = (self.instructions, self.cost);
+        let stop = self.execute(io_handler, tracer)?;
+        Ok(self.result(stop, start))
+    }
+
+    /// Describe a run which started with the given instruction and cost
+    /// counters.
+    #[inline(always)]
+    fn result(&self, stop: StopReason, (instructions, cost): (u64, u64)) -> RunResult {
+        RunResult {
+            pc: self.pc,
+            relative_base: self.relative_base,
+            stop,
+            instructions: self.instructions - instructions,
+            cost: self.cost - cost,
+        }
+    }
+
+    #[inline(always)]
+    fn execute(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+        tracer: &mut impl Tracer<M::Cell>,
+    ) -> Result<StopReason, IntcodeError> {
+        let result = loop {
+            match self.execute_instr(io_handler, tracer) {
+                Ok(None) => {}
+                Ok(Some(stop)) => break Ok(stop),
+                Err(e) => break Err(e),
+            }
+        };
+        self.last_stop = Some(result.unwrap_or_else(StopReason::Error));
+        result
+    }
+
+    /// Execute a single instruction.
+    ///
+    /// A stop reason is returned if the instruction stopped the machine, in
+    /// the same way as it would stop [`Self::run`].
+    #[inline(always)]
+    pub fn step(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+    ) -> Result<Option<StopReason>, IntcodeError> {
+        self.step_traced(io_handler, &mut NoTracer)
+    }
+
+    /// Execute a single instruction like [`Self::step`], passing it to the
+    /// tracer once it is done.
+    ///
+    /// An `Input` which has to wait for a value is not traced until it is
+    /// retried, and neither is an instruction which faults.
+    #[inline(always)]
+    pub fn step_traced(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+        tracer: &mut impl Tracer<M::Cell>,
+    ) -> Result<Option<StopReason>, IntcodeError> {
+        let result = self.execute_instr(io_handler, tracer);
+        self.last_stop = match result {
+            Ok(stop) => stop,
+            Err(e) => Some(StopReason::Error(e)),
+        };
+        result
+    }
+
+    #[inline(always)]
+    fn execute_instr(
+        &mut self,
+        io_handler: &mut impl IoBus<M::Cell>,
+        tracer: &mut impl Tracer<M::Cell>,
+    ) -> Result<Option<StopReason>, IntcodeError> {
+        if let Some(hit) = self.pending_hit.take() {
+            return Ok(Some(hit));
+        }
+
+        let pc = self.pc;
+        let instr = match self.cache.get(&self.memory, pc) {
+            Some(instr) => instr,
+            None => {
+                let instr = Instr::decode(&self.memory, pc)?;
+                self.cache.insert(pc, instr, self.memory.allocated());
+                instr
+            }
+        };
+        let cost = self.cost_model.cost(instr);
+        if let Some(budget) = self.budget {
+            if cost > budget {
+                return Ok(Some(StopReason::BudgetExhausted));
+            }
+        }
+
+        let mut trace = Trace::new(pc, instr, self.relative_base);
+        let mut next = pc + instr.size();
+        let mut stop = None;
+
+        match instr {
+            Instr::Hlt => stop = Some(StopReason::Halted),
 
             Instr::Add(augend, addend, sum) => {
-                let idx = sum.index(result.relative_base);
-                program.ensure_min(idx, 0);
-                program[idx] = augend.read(&program, result.relative_base)
-                    + addend.read(&program, result.relative_base);
+                let augend = self.read(&mut trace, 0, augend)?;
+                let addend = self.read(&mut trace, 1, addend)?;
+                let value = self
+                    .overflow
+                    .add(&augend, &addend)
+                    .ok_or_else(|| IntcodeError::overflow(pc, &augend, &addend))?;
+                self.write(&mut trace, 2, sum, value)?;
             }
 
             Instr::Mul(multiplicand, multiplier, product) => {
-                let idx = product.index(result.relative_base);
-                program.ensure_min(idx, 0);
-                program[idx] = multiplicand.read(&program, result.relative_base)
-                    * multiplier.read(&program, result.relative_base);
+                let multiplicand = self.read(&mut trace, 0, multiplicand)?;
+                let multiplier = self.read(&mut trace, 1, multiplier)?;
+                let value = self
+                    .overflow
+                    .mul(&multiplicand, &multiplier)
+                    .ok_or_else(|| IntcodeError::overflow(pc, &multiplicand, &multiplier))?;
+                self.write(&mut trace, 2, product, value)?;
             }
 
-            Instr::Input(dst) => match io_handler.input() {
-                None => {
-                    result.programmatic_break = true;
-                    result.pc += instr.size();
-                    return result;
-                }
+            Instr::Input(dst) => {
+                // Resolve the destination before asking the bus, so a
+                // faulting instruction never consumes any input.
+                self.index(pc, 0, dst)?;
+                match io_handler.input() {
+                    // The pc stays on the input instruction, so it is
+                    // retried once the machine is resumed.
+                    None => return Ok(Some(StopReason::NeedsInput)),
 
-                Some(i) => {
-                    let idx = dst.index(result.relative_base);
-                    program.ensure_min(idx, 0);
-                    program[idx] = i;
+                    Some(i) => self.write(&mut trace, 0, dst, i)?,
                 }
-            },
+            }
 
             Instr::Output(cell) => {
-                let value = cell.read(&program, result.relative_base);
+                let value = self.read(&mut trace, 0, cell)?;
                 if io_handler.output(value) {
-                    result.programmatic_break = true;
-                    result.pc += instr.size();
-                    return result;
+                    stop = Some(StopReason::OutputPaused);
                 }
             }
 
             Instr::JNZ(cell, dst) => {
-                let value = cell.read(&program, result.relative_base);
-                if value != 0 {
-                    inc = false;
-                    let pc = dst.read(&program, result.relative_base);
-                    debug_assert!(pc >= 0, "pc ({}; {:?}) must be >= 0", pc, dst);
-                    result.pc = pc as usize;
+                if self.read(&mut trace, 0, cell)? != M::Cell::default() {
+                    next = self.jump_target(&mut trace, 1, dst)?;
                 }
             }
 
             Instr::JZ(cell, dst) => {
-                let value = cell.read(&program, result.relative_base);
-                if value == 0 {
-                    inc = false;
-                    let pc = dst.read(&program, result.relative_base);
-                    debug_assert!(pc >= 0, "pc ({}; {:?}) must be >= 0", pc, dst);
-                    result.pc = pc as usize;
+                if self.read(&mut trace, 0, cell)? == M::Cell::default() {
+                    next = self.jump_target(&mut trace, 1, dst)?;
                 }
             }
 
             Instr::LT(lhs, rhs, dst) => {
-                let lhs = lhs.read(&program, result.relative_base);
-                let rhs = rhs.read(&program, result.relative_base);
-                let out = dst.index(result.relative_base);
-                program.ensure_min(out, 0);
-                program[out] = (lhs < rhs) as isize;
+                let value = self.read(&mut trace, 0, lhs)? < self.read(&mut trace, 1, rhs)?;
+                self.write(&mut trace, 2, dst, M::Cell::from_isize(value as isize))?;
             }
 
             Instr::EQ(lhs, rhs, dst) => {
-                let lhs = lhs.read(&program, result.relative_base);
-                let rhs = rhs.read(&program, result.relative_base);
-                let out = dst.index(result.relative_base);
-                program.ensure_min(out, 0);
-                program[out] = (lhs == rhs) as isize;
+                let value = self.read(&mut trace, 0, lhs)? == self.read(&mut trace, 1, rhs)?;
+                self.write(&mut trace, 2, dst, M::Cell::from_isize(value as isize))?;
             }
 
             Instr::ModRelBas(base) => {
-                let new = base.read(&program, result.relative_base);
-                debug_assert!(new >= 0, "base ({}; {:?}) must be >= 0", new, base);
-                result.relative_base += new as usize;
+                let old = self.relative_base;
+                let offset = self.read(&mut trace, 0, base)?.to_isize_saturating();
+                let new =
+                    (old as isize)
+                        .checked_add(offset)
+                        .ok_or(IntcodeError::AddressOverflow {
+                            pc,
+                            offset,
+                            param: 0,
+                        })?;
+                if new < 0 {
+                    return Err(IntcodeError::NegativeAddress {
+                        pc,
+                        value: new,
+                        param: 0,
+                    });
+                }
+                self.relative_base = new as usize;
+                trace.new_relative_base = Some(self.relative_base);
             }
         }
 
-        if inc {
-            result.pc += instr.size();
+        // Only instructions which are done are paid for; a starved input or a
+        // fault is free.
+        if let Some(budget) = &mut self.budget {
+            *budget -= cost;
         }
+        self.instructions += 1;
+        self.cost += cost;
+
+        self.pc = next;
+        tracer.trace(&trace);
+        if !self.watchpoints.is_empty() {
+            match (self.watch_hit(&trace), stop) {
+                (Some(hit), None) => stop = Some(hit),
+                (hit, Some(_)) => self.pending_hit = hit,
+                (None, None) => {}
+            }
+        }
+        Ok(stop)
+    }
+
+    /// The watchpoint the traced instruction stops the machine on, if any.
+    fn watch_hit(&self, trace: &Trace<M::Cell>) -> Option<StopReason> {
+        let watched = |kind, address| {
+            self.watchpoints.iter().flatten().any(|watchpoint| {
+                watchpoint.kind.covers(kind)
+                    && watchpoint.range.contains(address, trace.relative_base)
+            })
+        };
+        let hit = |address, old: &M::Cell, new: &M::Cell| StopReason::Watchpoint {
+            pc: trace.pc,
+            address,
+            old: old.to_isize_saturating(),
+            new: new.to_isize_saturating(),
+        };
+
+        if let Some(write) = &trace.write {
+            if watched(WatchKind::Write, write.address) {
+                return Some(hit(write.address, &write.old, &write.new));
+            }
+        }
+        trace
+            .reads()
+            .find(|&(address, _)| watched(WatchKind::Read, address))
+            .map(|(address, value)| hit(address, value, value))
+    }
+
+    /// Resolve the address the `param`th parameter of the instruction at `pc`
+    /// points to.
+    #[inline(always)]
+    fn index(&self, pc: usize, param: u32, mode: Mod) -> Result<usize, IntcodeError> {
+        match mode {
+            Mod::Immediate(_) => Err(IntcodeError::ImmediateDestination {
+                pc,
+                value: self.memory.peek(pc).to_isize_saturating(),
+                param,
+            }),
+            Mod::Position(idx) => Ok(idx),
+            Mod::Relative(offset) => {
+                let idx = (self.relative_base as isize)
+                    .checked_add(offset)
+                    .ok_or(IntcodeError::AddressOverflow { pc, offset, param })?;
+                if idx < 0 {
+                    return Err(IntcodeError::NegativeAddress {
+                        pc,
+                        value: idx,
+                        param,
+                    });
+                }
+
+                Ok(idx as usize)
+            }
+        }
+    }
+
+    /// Read the `param`th parameter of the traced instruction.
+    ///
+    /// Immediates are read from the cell of the parameter itself rather than
+    /// from the decoded instruction, which only holds them as an `isize`.
+    #[inline(always)]
+    fn read(
+        &self,
+        trace: &mut Trace<M::Cell>,
+        param: u32,
+        mode: Mod,
+    ) -> Result<M::Cell, IntcodeError> {
+        let value = match mode {
+            Mod::Immediate(_) => self.memory.peek(trace.pc + param as usize + 1),
+            _ => {
+                let idx = self.index(trace.pc, param, mode)?;
+                self.memory.peek(idx)
+            }
+        };
+        trace.operands[param as usize] = Some(value.clone());
+        Ok(value)
+    }
+
+    /// Write to the `param`th parameter of the traced instruction.
+    #[inline(always)]
+    fn write(
+        &mut self,
+        trace: &mut Trace<M::Cell>,
+        param: u32,
+        mode: Mod,
+        value: M::Cell,
+    ) -> Result<(), IntcodeError> {
+        let idx = self.index(trace.pc, param, mode)?;
+        let old = self.memory.peek(idx);
+        if !self.memory.poke(idx, value.clone(), self.memory_limit) {
+            return Err(IntcodeError::MemoryLimit {
+                pc: trace.pc,
+                address: idx,
+                param,
+            });
+        }
+        self.cache.invalidate(idx);
+        trace.write = Some(MemoryWrite {
+            address: idx,
+            old,
+            new: value,
+        });
+        Ok(())
     }
 
+    #[inline(always)]
+    fn jump_target(
+        &self,
+        trace: &mut Trace<M::Cell>,
+        param: u32,
+        mode: Mod,
+    ) -> Result<usize, IntcodeError> {
+        let target = self.read(trace, param, mode)?.to_isize_saturating();
+        if target < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                pc: trace.pc,
+                value: target,
+                param,
+            });
+        }
+
+        Ok(target as usize)
+    }
+}
+
+/// Run a program from the given `(pc, relative_base)` registers.
+///
+/// This is a thin wrapper around [`Machine::run`] for callers which keep the
+/// memory and registers around themselves.
+#[inline(always)]
+pub fn run<C: Cell>(
+    program: &mut Vec<C>,
+    registers: (usize, usize),
+    io_handler: &mut impl IoBus<C>,
+) -> RunResult {
+    with_machine(program, registers, |machine| machine.run(io_handler))
+}
+
+/// Run a program from the given `(pc, relative_base)` registers, returning any
+/// error separately.
+///
+/// See [`Machine::try_run`].
+pub fn try_run<C: Cell>(
+    program: &mut Vec<C>,
+    registers: (usize, usize),
+    io_handler: &mut impl IoBus<C>,
+) -> Result<RunResult, IntcodeError> {
+    with_machine(program, registers, |machine| machine.try_run(io_handler))
+}
+
+#[inline(always)]
+fn with_machine<C: Cell, T>(
+    program: &mut Vec<C>,
+    (pc, relative_base): (usize, usize),
+    f: impl FnOnce(&mut Machine<Vec<C>>) -> T,
+) -> T {
+    let mut machine = Machine::with_memory(std::mem::take(program));
+    machine.pc = pc;
+    machine.relative_base = relative_base;
+    let result = f(&mut machine);
+    *program = machine.memory;
     result
 }
 
-/// This defines an I/O bus for an intcode computer.
-pub trait IoBus {
+/// Read a program from a file of comma-separated cells, as the puzzle inputs
+/// are written.
+pub fn load_program(path: impl AsRef<std::path::Path>) -> Result<Vec<isize>, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+    source
+        .trim()
+        .split(',')
+        .enumerate()
+        .map(|(cell, value)| {
+            let value = value.trim();
+            value.parse().map_err(|_| LoadError::NotANumber {
+                cell,
+                value: value.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// An error raised by [`load_program`].
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+
+    /// The 0-indexed cell holds the `value`, which is not a number.
+    NotANumber {
+        cell: usize,
+        value: String,
+    },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "cannot read program: {}", e),
+            Self::NotANumber { cell, value } => {
+                write!(f, "cell {} is not a number: {}", cell, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An error raised when the machine hits something it cannot execute.
+///
+/// Every variant carries the `pc` of the faulting instruction. The `param` is
+/// the 0-indexed parameter of that instruction which caused the fault. Values
+/// of cells which don't fit an `isize` are saturated.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum IntcodeError {
+    /// The cell at `pc` holds the `value`, which is not a known instruction.
+    UnknownOpcode { pc: usize, value: isize },
+
+    /// The instruction `value` has an unknown mode for the parameter.
+    UnknownMode { pc: usize, value: isize, param: u32 },
+
+    /// The instruction `value` writes to a parameter in immediate mode.
+    ImmediateDestination { pc: usize, value: isize, param: u32 },
+
+    /// The parameter resolved to the negative address `value`.
+    ///
+    /// This is also raised for jumps to and relative bases of negative values.
+    NegativeAddress { pc: usize, value: isize, param: u32 },
+
+    /// Adding the `offset` to the relative base overflowed, so the parameter
+    /// resolved to no address at all.
+    ///
+    /// This is also raised for relative bases which overflow.
+    AddressOverflow {
+        pc: usize,
+        offset: isize,
+        param: u32,
+    },
+
+    /// Writing to the `address` would take the memory past its limit.
+    MemoryLimit {
+        pc: usize,
+        address: usize,
+        param: u32,
+    },
+
+    /// Adding or multiplying the operands `lhs` and `rhs` overflowed, while
+    /// the machine traps on [`Overflow`].
+    Overflow { pc: usize, lhs: isize, rhs: isize },
+}
+
+impl IntcodeError {
+    /// The program counter of the faulting instruction.
+    pub fn pc(&self) -> usize {
+        match *self {
+            Self::UnknownOpcode { pc, .. } => pc,
+            Self::UnknownMode { pc, .. } => pc,
+            Self::ImmediateDestination { pc, .. } => pc,
+            Self::NegativeAddress { pc, .. } => pc,
+            Self::AddressOverflow { pc, .. } => pc,
+            Self::MemoryLimit { pc, .. } => pc,
+            Self::Overflow { pc, .. } => pc,
+        }
+    }
+
+    #[inline(always)]
+    fn overflow<C: Cell>(pc: usize, lhs: &C, rhs: &C) -> Self {
+        Self::Overflow {
+            pc,
+            lhs: lhs.to_isize_saturating(),
+            rhs: rhs.to_isize_saturating(),
+        }
+    }
+}
+
+impl std::fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::UnknownOpcode { pc, value } => {
+                write!(f, "unknown op code {} at pc {}", value, pc)
+            }
+            Self::UnknownMode { pc, value, param } => write!(
+                f,
+                "unknown mode for parameter {} of {} at pc {}",
+                param, value, pc
+            ),
+            Self::ImmediateDestination { pc, value, param } => write!(
+                f,
+                "parameter {} of {} at pc {} is written to in immediate mode",
+                param, value, pc
+            ),
+            Self::NegativeAddress { pc, value, param } => write!(
+                f,
+                "parameter {} at pc {} resolved to negative address {}",
+                param, pc, value
+            ),
+            Self::AddressOverflow { pc, offset, param } => write!(
+                f,
+                "parameter {} at pc {} overflows the relative base by offset {}",
+                param, pc, offset
+            ),
+            Self::MemoryLimit { pc, address, param } => write!(
+                f,
+                "writing parameter {} at pc {} to address {} exceeds the memory limit",
+                param, pc, address
+            ),
+            Self::Overflow { pc, lhs, rhs } => {
+                write!(f, "operands {} and {} at pc {} overflow", lhs, rhs, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+/// This defines an I/O bus for an intcode computer, passing cells of type `C`.
+pub trait IoBus<C = isize> {
     /// An input instruction has been hit.
     ///
-    /// If a `None` value is returned, the computer will halt.
-    fn input(&mut self) -> Option<isize>;
+    /// If a `None` value is returned, the computer will stop with
+    /// [`StopReason::NeedsInput`].
+    fn input(&mut self) -> Option<C>;
+
+    /// An output instruction has been hit.
+    ///
+    /// If a `true` value is returned, the computer will stop with
+    /// [`StopReason::OutputPaused`].
+    fn output(&mut self, i: C) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct NoIoBusImpl {
+    pub panic: bool,
+}
+
+/// This defines a tracer, which is shown every instruction a machine executes.
+///
+/// See [`trace::TextTracer`] for a tracer writing a readable trace.
+pub trait Tracer<C = isize> {
+    /// An instruction has been executed.
+    fn trace(&mut self, trace: &Trace<C>);
+}
+
+/// A tracer which does nothing at all.
+///
+/// The machine runs with this tracer unless told otherwise; as the tracer is
+/// empty, recording the traces is optimised away entirely.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct NoTracer;
+
+/// Everything a single instruction did.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Trace<C = isize> {
+    pub pc: usize,
+    pub instr: Instr,
+
+    /// The values read from the parameters, indexed by parameter. Parameters
+    /// which are written to or not used are `None`, as is the destination of
+    /// a jump which isn't taken.
+    pub operands: [Option<C>; 3],
+
+    /// The cell written to, if any.
+    pub write: Option<MemoryWrite<C>>,
+
+    /// The relative base the instruction was executed with.
+    pub relative_base: usize,
+
+    /// The relative base after the instruction, if it was a `ModRelBas`.
+    pub new_relative_base: Option<usize>,
+}
+
+impl<C> Trace<C> {
+    #[inline(always)]
+    fn new(pc: usize, instr: Instr, relative_base: usize) -> Self {
+        Trace {
+            pc,
+            instr,
+            operands: [None, None, None],
+            write: None,
+            relative_base,
+            new_relative_base: None,
+        }
+    }
+
+    /// The addresses of the parameters the instruction read from.
+    pub fn read_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.reads().map(|(address, _)| address)
+    }
 
-    /// An output instruction has been hit.
-    ///
-    /// If a `true` value is returned, the computer will halt.
-    fn output(&mut self, i: isize) -> bool;
+    /// The addresses of the parameters the instruction read from, along with
+    /// the values read.
+    pub fn reads(&self) -> impl Iterator<Item = (usize, &C)> + '_ {
+        let relative_base = self.relative_base as isize;
+        let params = IntoIterator::into_iter(self.instr.params());
+        params
+            .zip(&self.operands)
+            .filter_map(move |(param, operand)| match (param?, operand.as_ref()?) {
+                (Mod::Immediate(_), _) => None,
+                (Mod::Position(address), value) => Some((address, value)),
+                // The machine faults on relative addresses which overflow or
+                // are negative, so this always fits.
+                (Mod::Relative(offset), value) => {
+                    Some((relative_base.wrapping_add(offset) as usize, value))
+                }
+            })
+    }
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
-pub struct NoIoBusImpl {
-    pub panic: bool,
+pub struct MemoryWrite<C = isize> {
+    pub address: usize,
+    pub old: C,
+    pub new: C,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -274,127 +1214,178 @@ impl Instr {
         }
     }
 
-    /// Parse an instruction.
+    /// The parameters of the instruction, by index.
+    #[inline(always)]
+    pub const fn params(self) -> [Option<Mod>; 3] {
+        match self {
+            Self::Add(a, b, c) | Self::Mul(a, b, c) | Self::LT(a, b, c) | Self::EQ(a, b, c) => {
+                [Some(a), Some(b), Some(c)]
+            }
+            Self::JNZ(a, b) | Self::JZ(a, b) => [Some(a), Some(b), None],
+            Self::Input(a) | Self::Output(a) | Self::ModRelBas(a) => [Some(a), None, None],
+            Self::Hlt => [None, None, None],
+        }
+    }
+
+    /// The op code of the instruction, without any modes.
+    pub const fn opcode(self) -> isize {
+        match self {
+            Self::Add(..) => 1,
+            Self::Mul(..) => 2,
+            Self::Input(..) => 3,
+            Self::Output(..) => 4,
+            Self::JNZ(..) => 5,
+            Self::JZ(..) => 6,
+            Self::LT(..) => 7,
+            Self::EQ(..) => 8,
+            Self::ModRelBas(..) => 9,
+            Self::Hlt => 99,
+        }
+    }
+
+    /// Encode the instruction back into the cells it is parsed from.
+    pub fn encode(self) -> Vec<isize> {
+        let params: &[Mod] = match self {
+            Self::Add(a, b, c) | Self::Mul(a, b, c) | Self::LT(a, b, c) | Self::EQ(a, b, c) => {
+                &[a, b, c]
+            }
+            Self::JNZ(a, b) | Self::JZ(a, b) => &[a, b],
+            Self::Input(a) | Self::Output(a) | Self::ModRelBas(a) => &[a],
+            Self::Hlt => &[],
+        };
+
+        let mut cells = vec![self.opcode()];
+        let mut scale = 100;
+        for param in params {
+            let (mode, value) = match *param {
+                Mod::Position(idx) => (0, idx as isize),
+                Mod::Immediate(i) => (1, i),
+                Mod::Relative(off) => (2, off),
+            };
+            cells[0] += mode * scale;
+            scale *= 10;
+            cells.push(value);
+        }
+
+        cells
+    }
+
+    /// Parse the instruction at `pc`.
     ///
-    /// The `program` parameter assumes it's a subslice of the entire program,
-    /// where the `0`th index is in fact from the program counter of the entire
-    /// program.
+    /// Cells past the end of the program are read as `0`, as they would be by
+    /// the machine.
+    #[inline(always)]
+    fn parse(program: &[isize], pc: usize) -> Result<Self, IntcodeError> {
+        Self::decode(program, pc)
+    }
+
+    /// Parse the instruction at `pc` from any memory backend.
     #[inline(always)]
-    fn parse(program: &[isize]) -> Self {
-        debug_assert!(program.len() >= 1);
-        let instr = program[0];
+    fn decode(program: &(impl Memory + ?Sized), pc: usize) -> Result<Self, IntcodeError> {
+        let cell = program.peek(pc);
+        let instr = match cell.to_isize() {
+            Some(instr) if instr >= 0 => instr,
+            _ => {
+                return Err(IntcodeError::UnknownOpcode {
+                    pc,
+                    value: cell.to_isize_saturating(),
+                })
+            }
+        };
 
-        match instr % 100 {
+        Ok(match instr % 100 {
             // Halt is the simplest operation to parse.
             99 => Instr::Hlt,
 
             1 => {
-                let augend = Mod::parse(program, 0);
-                let addend = Mod::parse(program, 1);
-                let sum = Mod::parse(program, 2);
+                let augend = Mod::parse(program, pc, 0)?;
+                let addend = Mod::parse(program, pc, 1)?;
+                let sum = Mod::parse(program, pc, 2)?;
                 Instr::Add(augend, addend, sum)
             }
 
             2 => {
-                let multiplicand = Mod::parse(program, 0);
-                let multiplier = Mod::parse(program, 1);
-                let product = Mod::parse(program, 2);
+                let multiplicand = Mod::parse(program, pc, 0)?;
+                let multiplier = Mod::parse(program, pc, 1)?;
+                let product = Mod::parse(program, pc, 2)?;
                 Instr::Mul(multiplicand, multiplier, product)
             }
 
-            3 => Instr::Input(Mod::parse(program, 0)),
+            3 => Instr::Input(Mod::parse(program, pc, 0)?),
 
-            4 => Instr::Output(Mod::parse(program, 0)),
+            4 => Instr::Output(Mod::parse(program, pc, 0)?),
 
             5 => {
-                let cell = Mod::parse(program, 0);
-                let destination = Mod::parse(program, 1);
+                let cell = Mod::parse(program, pc, 0)?;
+                let destination = Mod::parse(program, pc, 1)?;
                 Instr::JNZ(cell, destination)
             }
 
             6 => {
-                let cell = Mod::parse(program, 0);
-                let destination = Mod::parse(program, 1);
+                let cell = Mod::parse(program, pc, 0)?;
+                let destination = Mod::parse(program, pc, 1)?;
                 Instr::JZ(cell, destination)
             }
 
             7 => {
-                let lhs = Mod::parse(program, 0);
-                let rhs = Mod::parse(program, 1);
-                let result = Mod::parse(program, 2);
+                let lhs = Mod::parse(program, pc, 0)?;
+                let rhs = Mod::parse(program, pc, 1)?;
+                let result = Mod::parse(program, pc, 2)?;
                 Instr::LT(lhs, rhs, result)
             }
 
             8 => {
-                let lhs = Mod::parse(program, 0);
-                let rhs = Mod::parse(program, 1);
-                let result = Mod::parse(program, 2);
+                let lhs = Mod::parse(program, pc, 0)?;
+                let rhs = Mod::parse(program, pc, 1)?;
+                let result = Mod::parse(program, pc, 2)?;
                 Instr::EQ(lhs, rhs, result)
             }
 
-            9 => Instr::ModRelBas(Mod::parse(program, 0)),
+            9 => Instr::ModRelBas(Mod::parse(program, pc, 0)?),
 
-            op @ _ => panic!("Unsupported op: {} ({}) ({:?})", op, instr, program),
-        }
+            _ => return Err(IntcodeError::UnknownOpcode { pc, value: instr }),
+        })
     }
 }
 
 impl Mod {
+    /// Parse the mode of the `param`th parameter of the instruction at `pc`.
     #[inline(always)]
-    fn read(self, memory: &[isize], relbas: usize) -> isize {
-        match self {
-            Self::Immediate(i) => i,
-            _ => {
-                let idx = self.index(relbas);
-                if memory.len() <= idx {
-                    return 0;
-                }
-
-                memory[idx]
-            }
-        }
-    }
-
-    #[inline(always)]
-    fn index(self, relbas: usize) -> usize {
-        match self {
-            Self::Immediate(_) => panic!("Immediate is not an index"),
-            Self::Position(idx) => idx,
-            Self::Relative(idx) => {
-                let idx = idx + relbas as isize;
-                debug_assert!(idx >= 0);
-                idx as usize
-            }
-        }
-    }
-
-    #[inline(always)]
-    fn parse(program: &[isize], param: u32) -> Self {
+    fn parse(
+        program: &(impl Memory + ?Sized),
+        pc: usize,
+        param: u32,
+    ) -> Result<Self, IntcodeError> {
         // We'll have to cast the param a couple times, but that ensures we can
         // do compile-time bounds checking (i.e. it has to fit in the space of
         // 32-bits).
-
-        // We want at least the instruction (1 value) and the parameter (n
-        // values, 0-indexed), so we need to add 2 to accomodate for the instr
-        // and the 0-indexing.
-        debug_assert!(program.len() >= param as usize + 2);
-
-        let instr = program[0];
-        let read = program[param as usize + 1];
+        let instr = program.peek(pc).to_isize_saturating();
+        let read = program.peek(pc + param as usize + 1).to_isize_saturating();
         match instr.digit_at_pos(param + 2) {
             0 => {
-                debug_assert!(read >= 0, "a position must not be negative");
-                Self::Position(read as usize)
+                if read < 0 {
+                    return Err(IntcodeError::NegativeAddress {
+                        pc,
+                        value: read,
+                        param,
+                    });
+                }
+
+                Ok(Self::Position(read as usize))
             }
-            1 => Self::Immediate(read),
-            2 => Self::Relative(read),
-            n @ _ => panic!("Unknown mode: {}", n),
+            1 => Ok(Self::Immediate(read)),
+            2 => Ok(Self::Relative(read)),
+            _ => Err(IntcodeError::UnknownMode {
+                pc,
+                value: instr,
+                param,
+            }),
         }
     }
 }
 
-impl IoBus for NoIoBusImpl {
-    fn input(&mut self) -> Option<isize> {
+impl<C> IoBus<C> for NoIoBusImpl {
+    fn input(&mut self) -> Option<C> {
         if self.panic {
             panic!("I/O is not accepted")
         } else {
@@ -402,7 +1393,7 @@ impl IoBus for NoIoBusImpl {
         }
     }
 
-    fn output(&mut self, _: isize) -> bool {
+    fn output(&mut self, _: C) -> bool {
         if self.panic {
             panic!("I/O is not accepted")
         }
@@ -417,63 +1408,75 @@ impl Default for NoIoBusImpl {
     }
 }
 
-trait VecEnsureMin<T> {
-    fn ensure_min(&mut self, len: usize, value: T);
-}
-
-impl<T> VecEnsureMin<T> for Vec<T>
-where
-    T: Clone,
-{
-    fn ensure_min(&mut self, len: usize, value: T) {
-        if self.len() - 1 > len {
-            return;
-        }
-
-        self.resize(len + 1, value);
-    }
+impl<C> Tracer<C> for NoTracer {
+    #[inline(always)]
+    fn trace(&mut self, _: &Trace<C>) {}
 }
 
 #[test]
-#[should_panic]
 fn test_no_program() {
-    let _ = Instr::parse(&vec![]);
+    assert_eq!(
+        Instr::parse(&[], 0),
+        Err(IntcodeError::UnknownOpcode { pc: 0, value: 0 })
+    );
 }
 
 #[test]
-#[should_panic]
 fn test_index_on_immediate() {
-    let _ = Mod::Immediate(7).index(0);
+    let machine = Machine::new(vec![11101, 1, 1, 7, 99]);
+    assert_eq!(
+        machine.index(0, 2, Mod::Immediate(7)),
+        Err(IntcodeError::ImmediateDestination {
+            pc: 0,
+            value: 11101,
+            param: 2
+        })
+    );
 }
 
 #[test]
 fn test_instructions() {
-    assert_eq!(Instr::parse(&[99]), Instr::Hlt);
+    assert_eq!(Instr::parse(&[99], 0), Ok(Instr::Hlt));
+    assert_eq!(
+        Instr::parse(&[1, 1, 1, 1], 0),
+        Ok(Instr::Add(
+            Mod::Position(1),
+            Mod::Position(1),
+            Mod::Position(1)
+        ))
+    );
+    assert_eq!(
+        Instr::parse(&[1001, 1, 1, 1], 0),
+        Ok(Instr::Add(
+            Mod::Position(1),
+            Mod::Immediate(1),
+            Mod::Position(1)
+        ))
+    );
+    assert_eq!(Instr::parse(&[3, 1], 0), Ok(Instr::Input(Mod::Position(1))));
     assert_eq!(
-        Instr::parse(&[1, 1, 1, 1]),
-        Instr::Add(Mod::Position(1), Mod::Position(1), Mod::Position(1))
+        Instr::parse(&[4, 1], 0),
+        Ok(Instr::Output(Mod::Position(1)))
     );
     assert_eq!(
-        Instr::parse(&[1001, 1, 1, 1]),
-        Instr::Add(Mod::Position(1), Mod::Immediate(1), Mod::Position(1))
+        Instr::parse(&[99, 4, 1], 1),
+        Ok(Instr::Output(Mod::Position(1)))
     );
-    assert_eq!(Instr::parse(&[3, 1]), Instr::Input(Mod::Position(1)));
-    assert_eq!(Instr::parse(&[4, 1]), Instr::Output(Mod::Position(1)));
 }
 
 #[test]
 fn test_day2() {
-    let mut code = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+    let mut code: Vec<isize> = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
     let res = run(&mut code, (0, 0), &mut NoIoBusImpl::default());
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(code[0], 3500);
 
-    let mut code = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
+    let mut code: Vec<isize> = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
     let res = run(&mut code, (0, 0), &mut NoIoBusImpl::default());
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(code[0], 30);
 
-    let real = vec![
+    let real: Vec<isize> = vec![
         1, 0, 0, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 1, 13, 19, 1, 9, 19, 23, 2, 13, 23, 27,
         2, 27, 13, 31, 2, 31, 10, 35, 1, 6, 35, 39, 1, 5, 39, 43, 1, 10, 43, 47, 1, 5, 47, 51, 1,
         13, 51, 55, 2, 55, 9, 59, 1, 6, 59, 63, 1, 13, 63, 67, 1, 6, 67, 71, 1, 71, 10, 75, 2, 13,
@@ -485,13 +1488,13 @@ fn test_day2() {
     code[1] = 12;
     code[2] = 2;
     let res = run(&mut code, (0, 0), &mut NoIoBusImpl::default());
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(code[0], 3790689);
     let mut code = real.clone();
     code[1] = 12;
     code[2] = 2;
     let res = run(&mut code, (0, 0), &mut NoIoBusImpl::default());
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(code[0], 3790689);
 }
 
@@ -519,59 +1522,604 @@ fn test_day5() {
 
     let mut c = code.clone();
     let res = run(&mut c, (0, 0), &mut result);
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(result.1, 999);
 
     result.0 = 8;
     let res = run(&mut code.clone(), (0, 0), &mut result);
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(result.1, 1000);
 
     result.0 = 69;
     let res = run(&mut code.clone(), (0, 0), &mut result);
-    assert!(res.has_halted);
+    assert!(res.has_halted());
     assert_eq!(result.1, 1001);
 }
 
 #[test]
 fn test_day9() {
-    struct SimpleIoBus(bool, isize);
+    struct SimpleIoBus(bool, i64);
 
-    impl IoBus for SimpleIoBus {
-        fn input(&mut self) -> Option<isize> {
+    impl IoBus<i64> for SimpleIoBus {
+        fn input(&mut self) -> Option<i64> {
             panic!("No input allowed")
         }
 
-        fn output(&mut self, i: isize) -> bool {
+        fn output(&mut self, i: i64) -> bool {
             self.1 = i;
             self.0
         }
     }
 
-    let mut code = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
+    let mut code: Vec<i64> = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
     let mut result = SimpleIoBus(true, 0);
     let _ = run(&mut code, (0, 0), &mut result);
     assert_eq!(result.1, 34915192 * 34915192);
 
-    let mut code = vec![104, 125899906842624, 99];
+    let mut code: Vec<i64> = vec![104, 125899906842624, 99];
     let _ = run(&mut code, (0, 0), &mut result);
     assert_eq!(result.1, 125899906842624);
 
-    struct AnotherIoBus<'a>(std::slice::Iter<'a, isize>);
-    impl<'a> IoBus for AnotherIoBus<'a> {
-        fn input(&mut self) -> Option<isize> {
+    struct AnotherIoBus<'a>(std::slice::Iter<'a, i64>);
+    impl<'a> IoBus<i64> for AnotherIoBus<'a> {
+        fn input(&mut self) -> Option<i64> {
             panic!("No input allowed")
         }
 
-        fn output(&mut self, i: isize) -> bool {
+        fn output(&mut self, i: i64) -> bool {
             assert_eq!(i, *self.0.next().unwrap());
             false
         }
     }
 
-    let code = vec![
+    let code: Vec<i64> = vec![
         109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
     ];
     let iter = code.iter();
     let _ = run(&mut code.clone(), (0, 0), &mut AnotherIoBus(iter));
 }
+
+#[test]
+fn test_machine_resume() {
+    struct PausingIoBus(Vec<isize>);
+
+    impl IoBus for PausingIoBus {
+        fn input(&mut self) -> Option<isize> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            true
+        }
+    }
+
+    let mut machine = Machine::new(vec![104, 1, 109, 3, 204, 0, 99]);
+    let mut bus = PausingIoBus(Vec::new());
+
+    let res = machine.run(&mut bus);
+    assert_eq!(res.stop, StopReason::OutputPaused);
+    assert_eq!(machine.pc(), 2);
+    assert_eq!(bus.0, vec![1]);
+
+    let res = machine.run(&mut bus);
+    assert_eq!(res.stop, StopReason::OutputPaused);
+    assert_eq!(machine.relative_base(), 3);
+    assert_eq!(bus.0, vec![1, 3]);
+
+    let res = machine.run(&mut bus);
+    assert!(res.has_halted());
+    assert_eq!(machine.pc(), 7);
+}
+
+#[test]
+fn test_errors() {
+    let mut bus = NoIoBusImpl::default();
+
+    let mut machine = Machine::new(vec![1, 0, 0, 0, 42]);
+    assert_eq!(
+        machine.try_run(&mut bus),
+        Err(IntcodeError::UnknownOpcode { pc: 4, value: 42 })
+    );
+    assert_eq!(machine.pc(), 4);
+
+    let mut code: Vec<isize> = vec![3001, 0, 0, 0, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::UnknownMode {
+            pc: 0,
+            value: 3001,
+            param: 1
+        })
+    );
+
+    let mut code: Vec<isize> = vec![11101, 1, 1, 0, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::ImmediateDestination {
+            pc: 0,
+            value: 11101,
+            param: 2
+        })
+    );
+
+    let mut code: Vec<isize> = vec![1, -1, 0, 0, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::NegativeAddress {
+            pc: 0,
+            value: -1,
+            param: 0
+        })
+    );
+
+    let mut code: Vec<isize> = vec![109, 2, 22201, -5, 0, 0, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::NegativeAddress {
+            pc: 2,
+            value: -3,
+            param: 0
+        })
+    );
+
+    let mut code: Vec<isize> = vec![1105, 1, -7];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::NegativeAddress {
+            pc: 0,
+            value: -7,
+            param: 1
+        })
+    );
+
+    let mut code: Vec<isize> = vec![109, 1, 204, isize::MAX, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::AddressOverflow {
+            pc: 2,
+            offset: isize::MAX,
+            param: 0
+        })
+    );
+
+    let mut code: Vec<isize> = vec![109, 1, 109, isize::MAX, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::AddressOverflow {
+            pc: 2,
+            offset: isize::MAX,
+            param: 0
+        })
+    );
+
+    // Lowering the relative base is fine, as long as it stays non-negative.
+    let mut code: Vec<isize> = vec![109, 5, 109, -3, 109, -3, 99];
+    assert_eq!(
+        try_run(&mut code, (0, 0), &mut bus),
+        Err(IntcodeError::NegativeAddress {
+            pc: 4,
+            value: -1,
+            param: 0
+        })
+    );
+}
+
+#[test]
+fn test_stop_reasons() {
+    struct StarvingIoBus;
+
+    impl IoBus for StarvingIoBus {
+        fn input(&mut self) -> Option<isize> {
+            None
+        }
+
+        fn output(&mut self, _: isize) -> bool {
+            true
+        }
+    }
+
+    let res = run(&mut vec![99], (0, 0), &mut StarvingIoBus);
+    assert_eq!(res.stop, StopReason::Halted);
+
+    let res = run(&mut vec![3, 0, 99], (0, 0), &mut StarvingIoBus);
+    assert_eq!(res.stop, StopReason::NeedsInput);
+    assert_eq!(res.pc, 0);
+
+    let res = run(&mut vec![104, 0, 99], (0, 0), &mut StarvingIoBus);
+    assert_eq!(res.stop, StopReason::OutputPaused);
+
+    let res = run(&mut vec![1, 0, 0, 0, 42], (0, 0), &mut StarvingIoBus);
+    assert_eq!(
+        res.stop,
+        StopReason::Error(IntcodeError::UnknownOpcode { pc: 4, value: 42 })
+    );
+    assert_eq!(res.pc, 4);
+}
+
+#[test]
+fn test_resume_after_starvation() {
+    struct QueueIoBus(Vec<isize>, Vec<isize>);
+
+    impl IoBus for QueueIoBus {
+        fn input(&mut self) -> Option<isize> {
+            self.0.pop()
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.1.push(i);
+            false
+        }
+    }
+
+    // Reads two values and outputs their sum.
+    let mut machine = Machine::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+    let mut bus = QueueIoBus(vec![], vec![]);
+
+    assert_eq!(machine.run(&mut bus).stop, StopReason::NeedsInput);
+    assert_eq!(machine.pc(), 0);
+
+    bus.0.push(3);
+    assert_eq!(machine.run(&mut bus).stop, StopReason::NeedsInput);
+    assert_eq!(machine.pc(), 2);
+
+    bus.0.push(4);
+    assert!(machine.run(&mut bus).has_halted());
+    assert_eq!(bus.1, vec![7]);
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut machine = Machine::new(vec![1101, 1, 2, 1000, 99]);
+    machine.set_memory_limit(1000);
+    assert_eq!(
+        machine.try_run(&mut NoIoBusImpl::default()),
+        Err(IntcodeError::MemoryLimit {
+            pc: 0,
+            address: 1000,
+            param: 2
+        })
+    );
+
+    machine.set_memory_limit(1001);
+    assert!(machine.run(&mut NoIoBusImpl::default()).has_halted());
+    assert_eq!(machine.memory()[1000], 3);
+}
+
+#[test]
+fn test_paged_memory() {
+    struct CollectIoBus(Vec<isize>);
+
+    impl IoBus for CollectIoBus {
+        fn input(&mut self) -> Option<isize> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    // Writes far away and reads it back as output.
+    let program = vec![1101, 5, 6, 1_000_000_000_000, 4, 1_000_000_000_000, 99];
+    let mut machine = Machine::with_memory(memory::PagedMemory::from(program));
+    let mut bus = CollectIoBus(vec![]);
+    assert!(machine.run(&mut bus).has_halted());
+    assert_eq!(bus.0, vec![11]);
+    assert_eq!(machine.memory().allocated(), 2 * memory::PAGE_SIZE);
+}
+
+#[test]
+fn test_budget() {
+    // Counts down from 3, outputting every number.
+    let program = vec![109, 12, 204, 0, 21201, 0, -1, 0, 1205, 0, 2, 99, 3];
+
+    struct CollectIoBus(Vec<isize>);
+
+    impl IoBus for CollectIoBus {
+        fn input(&mut self) -> Option<isize> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    let mut machine = Machine::new(program.clone());
+    let mut bus = CollectIoBus(vec![]);
+    let res = machine.run(&mut bus);
+    assert!(res.has_halted());
+    assert_eq!((res.instructions, res.cost), (11, 11));
+
+    let mut machine = Machine::new(program.clone());
+    let mut bus = CollectIoBus(vec![]);
+    machine.set_budget(Some(5));
+    let res = machine.run(&mut bus);
+    assert_eq!(res.stop, StopReason::BudgetExhausted);
+    assert_eq!((res.pc, res.instructions, res.cost), (4, 5, 5));
+    assert_eq!(bus.0, vec![3, 2]);
+    assert_eq!(machine.budget(), Some(0));
+
+    machine.grant_budget(100);
+    let res = machine.run(&mut bus);
+    assert!(res.has_halted());
+    assert_eq!((res.instructions, res.cost), (6, 6));
+    assert_eq!(bus.0, vec![3, 2, 1]);
+    assert_eq!(machine.budget(), Some(94));
+    assert_eq!(machine.instructions(), 11);
+
+    // Outputs are expensive, halting is free.
+    let mut machine = Machine::new(program);
+    let mut bus = CollectIoBus(vec![]);
+    machine.set_cost_model(CostModel::Weighted([0, 1, 1, 1, 10, 1, 1, 1, 1, 1]));
+    machine.set_budget(Some(12));
+    let res = machine.run(&mut bus);
+    assert_eq!(res.stop, StopReason::BudgetExhausted);
+    assert_eq!((res.pc, res.cost), (8, 12));
+
+    machine.grant_budget(25);
+    let res = machine.run(&mut bus);
+    assert!(res.has_halted());
+    assert_eq!(res.cost, 25);
+    assert_eq!(machine.cost(), 37);
+    assert_eq!(machine.budget(), Some(0));
+}
+
+#[test]
+fn test_self_modifying() {
+    struct CollectIoBus(Vec<isize>);
+
+    impl IoBus for CollectIoBus {
+        fn input(&mut self) -> Option<isize> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    // Output the parameter of the first instruction, then add one to it and
+    // loop until it reaches 4.
+    let program = vec![104, 1, 1001, 1, 1, 1, 1008, 1, 4, 14, 1006, 14, 0, 99, 0];
+    let mut machine = Machine::new(program);
+    let mut bus = CollectIoBus(vec![]);
+    assert!(machine.run(&mut bus).has_halted());
+    assert_eq!(bus.0, vec![1, 2, 3]);
+
+    // Writes through the memory are seen as well.
+    let mut machine = Machine::new(vec![104, 1, 1105, 1, 0]);
+    machine.step(&mut bus).unwrap();
+    machine.step(&mut bus).unwrap();
+    machine.memory_mut()[1] = 5;
+    assert_eq!(machine.step(&mut bus), Ok(None));
+    assert_eq!(bus.0, vec![1, 2, 3, 1, 5]);
+}
+
+#[test]
+fn test_cells() {
+    use self::cell::BigInt;
+
+    struct CollectIoBus<C>(Vec<C>);
+
+    impl<C> IoBus<C> for CollectIoBus<C> {
+        fn input(&mut self) -> Option<C> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: C) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    // Square the immediate, then square the square.
+    fn squares<C: Cell>(value: &str) -> Vec<C> {
+        let mut code = vec![1102, 0, 0, 13, 4, 13, 2, 13, 13, 13, 4, 13, 99, 0]
+            .into_iter()
+            .map(C::from_isize)
+            .collect::<Vec<_>>();
+        code[1] = value.parse().ok().unwrap();
+        code[2] = code[1].clone();
+        let mut bus = CollectIoBus(vec![]);
+        assert!(run(&mut code, (0, 0), &mut bus).has_halted());
+        bus.0
+    }
+
+    assert_eq!(squares::<i64>("-1000"), vec![1_000_000, 1_000_000_000_000]);
+    assert_eq!(
+        squares::<i128>("34915192"),
+        vec![1219070632396864, 1486133206772489918753597034496]
+    );
+    let big = squares::<BigInt>("-1125899906842624");
+    assert_eq!(
+        big.iter().map(BigInt::to_string).collect::<Vec<_>>(),
+        vec![
+            "1267650600228229401496703205376",
+            "1606938044258990275541962092341162602522202993782792835301376"
+        ]
+    );
+
+    // Large cells still compare and jump like any other.
+    let big = "100000000000000000000000000000".parse::<BigInt>().unwrap();
+    let mut code = vec![1007, 7, 0, 7, 4, 7, 99]
+        .into_iter()
+        .map(BigInt::from_isize)
+        .collect::<Vec<_>>();
+    code[2] = big;
+    let mut bus = CollectIoBus(vec![]);
+    assert!(run(&mut code, (0, 0), &mut bus).has_halted());
+    assert_eq!(bus.0, vec![BigInt::from_isize(1)]);
+
+    // An op code which doesn't fit is saturated in the error.
+    let mut machine =
+        Machine::with_memory(vec!["-100000000000000000000000".parse::<BigInt>().unwrap()]);
+    assert_eq!(
+        machine.try_run(&mut NoIoBusImpl::default()),
+        Err(IntcodeError::UnknownOpcode {
+            pc: 0,
+            value: isize::MIN
+        })
+    );
+}
+
+#[test]
+fn test_overflow() {
+    let program = vec![1101, isize::MAX, 1, 9, 1002, 9, 2, 9, 99, 0];
+
+    let mut machine = Machine::new(program.clone());
+    assert_eq!(
+        machine.try_run(&mut NoIoBusImpl::default()),
+        Err(IntcodeError::Overflow {
+            pc: 0,
+            lhs: isize::MAX,
+            rhs: 1
+        })
+    );
+    assert_eq!(machine.pc(), 0);
+
+    let mut machine = Machine::new(program.clone());
+    machine.set_overflow(Overflow::Wrap);
+    assert!(machine.run(&mut NoIoBusImpl::default()).has_halted());
+    assert_eq!(machine.memory()[9], 0);
+
+    let mut machine = Machine::new(program);
+    machine.set_overflow(Overflow::Saturate);
+    assert!(machine.run(&mut NoIoBusImpl::default()).has_halted());
+    assert_eq!(machine.memory()[9], isize::MAX);
+}
+
+#[test]
+fn test_watchpoints() {
+    struct QueueIoBus(Vec<isize>, Vec<isize>);
+
+    impl IoBus for QueueIoBus {
+        fn input(&mut self) -> Option<isize> {
+            self.0.pop()
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.1.push(i);
+            false
+        }
+    }
+
+    // Doubles the input in place, then outputs it relative to its address.
+    let program = asm::assemble(
+        "
+        in   [x]
+        mul  [x], #2, [x]
+        arb  #x
+        out  rb+0
+        hlt
+x:      .data 0
+",
+    )
+    .unwrap();
+    let watchpoint = |pc, old, new| StopReason::Watchpoint {
+        pc,
+        address: 11,
+        old,
+        new,
+    };
+
+    let mut machine = Machine::new(program.clone());
+    let mut bus = QueueIoBus(vec![5], vec![]);
+    let written = machine.watch(WatchKind::Write, 11..12);
+    let read = machine.watch_relative(WatchKind::Read, 0..1);
+    let res = machine.run(&mut bus);
+    assert_eq!(res.stop, watchpoint(0, 0, 5));
+    assert_eq!((res.pc, res.instructions), (2, 1));
+    assert_eq!(machine.last_stop(), Some(watchpoint(0, 0, 5)));
+
+    // The write is reported rather than the read of the same cell.
+    assert_eq!(machine.run(&mut bus).stop, watchpoint(2, 5, 10));
+    assert!(machine.unwatch(written));
+    assert!(!machine.unwatch(written));
+
+    // The output is still sent when the relative watchpoint stops it.
+    assert_eq!(machine.run(&mut bus).stop, watchpoint(8, 10, 10));
+    assert_eq!(bus.1, vec![10]);
+    assert_eq!(machine.watchpoints().count(), 1);
+    assert!(machine.unwatch(read));
+    assert!(machine.run(&mut bus).has_halted());
+
+    let mut machine = Machine::new(program);
+    let mut bus = QueueIoBus(vec![5], vec![]);
+    machine.watch(WatchKind::Access, 10..12);
+    let mut stops = Vec::new();
+    while let res @ RunResult {
+        stop: StopReason::Watchpoint { pc, .. },
+        ..
+    } = machine.run(&mut bus)
+    {
+        stops.push((pc, res.pc));
+    }
+    assert_eq!(machine.last_stop(), Some(StopReason::Halted));
+    assert_eq!(stops, vec![(0, 2), (2, 6), (8, 10)]);
+    assert_eq!(bus.1, vec![10]);
+
+    struct PausingIoBus(Vec<isize>);
+
+    impl IoBus for PausingIoBus {
+        fn input(&mut self) -> Option<isize> {
+            None
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            true
+        }
+    }
+
+    // The pause comes first, and the watchpoint once the machine resumes.
+    let mut machine = Machine::new(vec![4, 3, 99, 7]);
+    let mut bus = PausingIoBus(vec![]);
+    machine.watch(WatchKind::Read, 3..4);
+    let res = machine.run(&mut bus);
+    assert_eq!((res.stop, res.pc), (StopReason::OutputPaused, 2));
+    assert_eq!(bus.0, vec![7]);
+    let res = machine.run(&mut bus);
+    assert_eq!(
+        res.stop,
+        StopReason::Watchpoint {
+            pc: 0,
+            address: 3,
+            old: 7,
+            new: 7
+        }
+    );
+    assert_eq!((res.pc, res.instructions), (2, 0));
+    assert!(machine.run(&mut bus).has_halted());
+
+    // A relative address which overflows is a fault rather than a read.
+    let mut machine = Machine::new(vec![109, 1, 204, isize::MAX, 99]);
+    machine.watch(WatchKind::Read, 0..usize::MAX);
+    assert_eq!(
+        machine.run(&mut bus).stop,
+        StopReason::Error(IntcodeError::AddressOverflow {
+            pc: 2,
+            offset: isize::MAX,
+            param: 0
+        })
+    );
+}
+
+#[test]
+fn test_load_program() {
+    let path = std::env::temp_dir().join(format!("intcode-load-{}.txt", std::process::id()));
+    std::fs::write(&path, "1, 0,-3,99\n").unwrap();
+    assert_eq!(load_program(&path).unwrap(), vec![1, 0, -3, 99]);
+
+    std::fs::write(&path, "1,0,x\n").unwrap();
+    assert_eq!(
+        load_program(&path).unwrap_err().to_string(),
+        "cell 2 is not a number: x"
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(load_program(&path), Err(LoadError::Io(_))));
+}
@@ -0,0 +1,587 @@
+//! # aot - intcode programs compiled ahead of time into Rust
+//!
+//! [`transpile`] turns a program into the source of a Rust module with a
+//! `run` function of the same signature as [`super::run`], so the native
+//! version can be swapped in for the interpreter and compared against it.
+//!
+//! The program is walked as by [`Cfg`], which is also entered right after
+//! any instruction whose end is used as an immediate elsewhere (which is how
+//! programs push return addresses). The blocks of the graph, along with any
+//! instruction whose address is used as an immediate, start a block of
+//! straight-line Rust; jumps between blocks go through a single `match` on
+//! the pc.
+//!
+//! Everything else is left to an embedded interpreter, which takes over with
+//! the current registers:
+//!
+//! * a jump to an address which doesn't start a block, e.g. a computed jump
+//!   to an address which is never named as an immediate;
+//! * an instruction which cannot be decoded statically, or which writes to an
+//!   immediate;
+//! * any write into the cells of a translated instruction, after which the
+//!   translation no longer matches the program. `run` also checks the code on
+//!   entry, so a program whose code was changed before is interpreted from the
+//!   start.
+//!
+//! Checking the code takes a pass over all of it, so the generated module
+//! also has a `resume`, which continues a run that stopped for I/O without
+//! checking again. It may only be used as long as nothing, neither the caller
+//! nor the program itself, has written into the code since `run` checked it.
+//!
+//! The generated `run` always uses the default memory limit, traps on
+//! overflow, and counts every instruction with a cost of `1`.
+use super::cfg::Cfg;
+use super::{Instr, Mod};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+/// What is known about a program before it runs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Analysis {
+    /// Every instruction reachable through static control flow.
+    instrs: BTreeMap<usize, Instr>,
+
+    /// The addresses which start a block.
+    leaders: BTreeSet<usize>,
+
+    /// Whether each cell belongs to an instruction in `instrs`.
+    code: Vec<bool>,
+}
+
+impl Analysis {
+    fn new(program: &[isize]) -> Self {
+        // A return address is pushed as the address right after the jump
+        // into the subroutine, which nothing else may lead to.
+        let mut entries = BTreeSet::new();
+        let cfg = loop {
+            let cfg = Cfg::with_entries(program, entries.iter().copied());
+            let instrs = cfg.blocks.values().flat_map(|block| &block.instrs);
+            let ends: BTreeSet<_> = instrs
+                .clone()
+                .map(|&(pc, instr)| pc + instr.size())
+                .collect();
+            let returns: Vec<_> = immediates(instrs)
+                .filter(|address| ends.contains(address) && !entries.contains(address))
+                .collect();
+            if returns.is_empty() {
+                break cfg;
+            }
+            entries.extend(returns);
+        };
+
+        let mut analysis = Analysis::default();
+        for block in cfg.blocks.values() {
+            analysis.instrs.extend(block.instrs.iter().copied());
+        }
+        analysis.leaders.extend(cfg.blocks.keys().copied());
+        let instrs = cfg.blocks.values().flat_map(|block| &block.instrs);
+        for address in immediates(instrs) {
+            if analysis.instrs.contains_key(&address) {
+                analysis.leaders.insert(address);
+            }
+        }
+
+        let mut code = vec![false; program.len()];
+        for (&pc, instr) in &analysis.instrs {
+            for cell in &mut code[pc..pc + instr.size()] {
+                *cell = true;
+            }
+        }
+        if let Some(end) = code.iter().rposition(|&cell| cell) {
+            code.truncate(end + 1);
+        } else {
+            code.clear();
+        }
+        analysis.code = code;
+
+        analysis
+    }
+
+    #[inline(always)]
+    fn is_code(&self, address: usize) -> bool {
+        self.code.get(address).copied().unwrap_or(false)
+    }
+}
+
+/// Every immediate of the instructions which may be an address.
+fn immediates<'a>(
+    instrs: impl Iterator<Item = &'a (usize, Instr)> + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    instrs
+        .flat_map(|(_, instr)| IntoIterator::into_iter(instr.params()))
+        .filter_map(|param| match param? {
+            Mod::Immediate(value) if value >= 0 => Some(value as usize),
+            _ => None,
+        })
+}
+
+/// Translate the program into the source of a Rust module.
+///
+/// The `intcode` path is how the generated module reaches this module, e.g.
+/// `aoc2019::intcode` or `crate::intcode`.
+pub fn transpile(program: &[isize], intcode: &str) -> String {
+    let analysis = Analysis::new(program);
+    let mut out = String::new();
+
+    writeln!(out, "//! Compiled ahead of time from an intcode program.").unwrap();
+    writeln!(out, "#![allow(unused, arithmetic_overflow, clippy::all)]").unwrap();
+    writeln!(
+        out,
+        "use {}::memory::{{Memory, DEFAULT_MEMORY_LIMIT}};",
+        intcode
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "use {}::{{self as intcode, IntcodeError, IoBus, RunResult, StopReason}};",
+        intcode
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "/// The program this module was compiled from.").unwrap();
+    writeln!(out, "pub const PROGRAM: &[isize] = &[{}];", join(program)).unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "/// Whether each cell belongs to a compiled instruction."
+    )
+    .unwrap();
+    let code = analysis
+        .code
+        .iter()
+        .map(|&cell| cell as u8)
+        .collect::<Vec<_>>();
+    writeln!(out, "const CODE: &[u8] = &[{}];", join(&code)).unwrap();
+    out.push_str(HELPERS);
+
+    out.push_str(RUN_HEAD);
+    for &leader in &analysis.leaders {
+        if analysis.instrs.contains_key(&leader) {
+            block(&mut out, &analysis, leader);
+        }
+    }
+    out.push_str(RUN_TAIL);
+
+    out
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const HELPERS: &str = r#"
+#[inline(always)]
+fn relative(rb: usize, offset: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    let address = (rb as isize)
+        .checked_add(offset)
+        .ok_or(IntcodeError::AddressOverflow { pc, offset, param })?;
+    if address < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value: address, param });
+    }
+    Ok(address as usize)
+}
+
+#[inline(always)]
+fn add(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_add(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn mul(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_mul(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn target(value: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    if value < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value, param });
+    }
+    Ok(value as usize)
+}
+
+#[inline(always)]
+fn poke(
+    memory: &mut Vec<isize>,
+    address: usize,
+    value: isize,
+    pc: usize,
+    param: u32,
+) -> Result<(), IntcodeError> {
+    if !memory.poke(address, value, DEFAULT_MEMORY_LIMIT) {
+        return Err(IntcodeError::MemoryLimit { pc, address, param });
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn is_code(address: usize) -> bool {
+    CODE.get(address).copied().unwrap_or(0) != 0
+}
+"#;
+
+const RUN_HEAD: &str = r#"
+/// Run the program like `intcode::run`, which takes over for anything not compiled.
+pub fn run(memory: &mut Vec<isize>, registers: (usize, usize), io: &mut impl IoBus) -> RunResult {
+    if (0..CODE.len()).any(|i| CODE[i] != 0 && memory.peek(i) != PROGRAM[i]) {
+        return intcode::run(memory, registers, io);
+    }
+    resume(memory, registers, io)
+}
+
+/// Continue a run like `run`, without checking the code against the program.
+///
+/// Nothing may have written into the code since `run` last checked it.
+pub fn resume(
+    memory: &mut Vec<isize>,
+    (mut pc, mut rb): (usize, usize),
+    io: &mut impl IoBus,
+) -> RunResult {
+    let mut n = 0;
+    let result = (|| -> Result<Option<StopReason>, IntcodeError> {
+        loop {
+            match pc {
+"#;
+
+const RUN_TAIL: &str = r#"                _ => return Ok(None),
+            }
+        }
+    })();
+
+    let stop = match result {
+        Ok(Some(stop)) => stop,
+        Ok(None) => {
+            let mut result = intcode::run(memory, (pc, rb), io);
+            result.instructions += n;
+            result.cost += n;
+            return result;
+        }
+        Err(e) => {
+            pc = e.pc();
+            StopReason::Error(e)
+        }
+    };
+    RunResult {
+        pc,
+        relative_base: rb,
+        stop,
+        instructions: n,
+        cost: n,
+    }
+}
+"#;
+
+/// The indentation of the statements in a block.
+const INDENT: &str = "                    ";
+
+/// Write the block starting at `leader`, up to the next block or the first
+/// instruction which leaves it.
+fn block(out: &mut String, analysis: &Analysis, leader: usize) {
+    writeln!(out, "                {} => {{", leader).unwrap();
+    let mut pc = leader;
+    loop {
+        let instr = match analysis.instrs.get(&pc) {
+            Some(&instr) => instr,
+            None => {
+                line(out, &format!("pc = {};", pc));
+                line(out, "return Ok(None);");
+                break;
+            }
+        };
+        if pc != leader && analysis.leaders.contains(&pc) {
+            line(out, &format!("pc = {};", pc));
+            line(out, "continue;");
+            break;
+        }
+
+        line(out, &format!("// {:04}: {}", pc, instr));
+        if !translate(out, analysis, pc, instr) {
+            break;
+        }
+        pc += instr.size();
+    }
+    writeln!(out, "                }}").unwrap();
+}
+
+fn line(out: &mut String, text: &str) {
+    out.push_str(INDENT);
+    out.push_str(text);
+    out.push('\n');
+}
+
+/// Write a single instruction. Returns whether the block may continue past it.
+fn translate(out: &mut String, analysis: &Analysis, pc: usize, instr: Instr) -> bool {
+    let next = pc + instr.size();
+    let read = |param: u32, mode: Mod| match mode {
+        Mod::Immediate(i) if i < 0 => format!("({})", i),
+        Mod::Immediate(i) => i.to_string(),
+        Mod::Position(idx) => format!("memory.peek({})", idx),
+        Mod::Relative(off) => format!("memory.peek(relative(rb, {}, {}, {})?)", off, pc, param),
+    };
+    let address = |param: u32, mode: Mod| match mode {
+        Mod::Immediate(_) => None,
+        Mod::Position(idx) => Some(idx.to_string()),
+        Mod::Relative(off) => Some(format!("relative(rb, {}, {}, {})?", off, pc, param)),
+    };
+    // The interpreter raises the error of a write to an immediate.
+    let interpret = |out: &mut String| {
+        line(out, &format!("pc = {};", pc));
+        line(out, "return Ok(None);");
+        false
+    };
+    // A write into the code leaves the rest to the interpreter.
+    let written = |out: &mut String, mode: Mod| match mode {
+        Mod::Position(idx) if analysis.is_code(idx) => {
+            line(out, &format!("pc = {};", next));
+            line(out, "return Ok(None);");
+            false
+        }
+        Mod::Relative(_) => {
+            line(out, "if is_code(address) {");
+            line(out, &format!("    pc = {};", next));
+            line(out, "    return Ok(None);");
+            line(out, "}");
+            true
+        }
+        _ => true,
+    };
+
+    match instr {
+        Instr::Add(a, b, dst)
+        | Instr::Mul(a, b, dst)
+        | Instr::LT(a, b, dst)
+        | Instr::EQ(a, b, dst) => {
+            let (a, b) = (read(0, a), read(1, b));
+            let value = match instr {
+                Instr::Add(..) => format!("add({}, {}, {})?", a, b, pc),
+                Instr::Mul(..) => format!("mul({}, {}, {})?", a, b, pc),
+                Instr::LT(..) => format!("({} < {}) as isize", a, b),
+                _ => format!("({} == {}) as isize", a, b),
+            };
+            let address = match address(2, dst) {
+                Some(address) => address,
+                None => return interpret(out),
+            };
+            line(out, &format!("let value = {};", value));
+            line(out, &format!("let address = {};", address));
+            line(out, &format!("poke(memory, address, value, {}, 2)?;", pc));
+            line(out, "n += 1;");
+            written(out, dst)
+        }
+
+        Instr::Input(dst) => {
+            let address = match address(0, dst) {
+                Some(address) => address,
+                None => return interpret(out),
+            };
+            line(out, &format!("let address = {};", address));
+            line(out, "match io.input() {");
+            line(
+                out,
+                &format!(
+                    "    Some(value) => poke(memory, address, value, {}, 0)?,",
+                    pc
+                ),
+            );
+            line(out, "    None => {");
+            line(out, &format!("        pc = {};", pc));
+            line(out, "        return Ok(Some(StopReason::NeedsInput));");
+            line(out, "    }");
+            line(out, "}");
+            line(out, "n += 1;");
+            written(out, dst)
+        }
+
+        Instr::Output(cell) => {
+            line(out, &format!("let value = {};", read(0, cell)));
+            line(out, "n += 1;");
+            line(out, "if io.output(value) {");
+            line(out, &format!("    pc = {};", next));
+            line(out, "    return Ok(Some(StopReason::OutputPaused));");
+            line(out, "}");
+            true
+        }
+
+        Instr::JNZ(cell, dst) | Instr::JZ(cell, dst) => {
+            let jump_if_zero = matches!(instr, Instr::JZ(..));
+            let target = match dst {
+                Mod::Immediate(t) if t < 0 => format!(
+                    "return Err(IntcodeError::NegativeAddress {{ pc: {}, value: {}, param: 1 }})",
+                    pc, t
+                ),
+                Mod::Immediate(t) => t.to_string(),
+                _ => format!("target({}, {}, 1)?", read(1, dst), pc),
+            };
+            let jump = |out: &mut String, indent: &str| {
+                line(out, &format!("{}let target = {};", indent, target));
+                line(out, &format!("{}n += 1;", indent));
+                line(out, &format!("{}pc = target;", indent));
+                line(out, &format!("{}continue;", indent));
+            };
+
+            match cell {
+                Mod::Immediate(i) if (i == 0) == jump_if_zero => {
+                    jump(out, "");
+                    false
+                }
+                Mod::Immediate(_) => {
+                    line(out, "n += 1;");
+                    true
+                }
+                _ => {
+                    let op = if jump_if_zero { "==" } else { "!=" };
+                    line(out, &format!("if {} {} 0 {{", read(0, cell), op));
+                    jump(out, "    ");
+                    line(out, "}");
+                    line(out, "n += 1;");
+                    true
+                }
+            }
+        }
+
+        Instr::ModRelBas(base) => {
+            line(
+                out,
+                &format!("rb = relative(rb, {}, {}, 0)?;", read(0, base), pc),
+            );
+            line(out, "n += 1;");
+            true
+        }
+
+        Instr::Hlt => {
+            line(out, "n += 1;");
+            line(out, &format!("pc = {};", next));
+            line(out, "return Ok(Some(StopReason::Halted));");
+            false
+        }
+    }
+}
+
+/// A program compiled by `intaot`, to run the generated code in the tests.
+#[cfg(test)]
+#[rustfmt::skip]
+mod double;
+
+/// A long loop compiled by `intaot`, to time the generated code against the
+/// interpreter.
+#[cfg(test)]
+#[rustfmt::skip]
+mod sum;
+
+#[test]
+fn test_analysis() {
+    // Push the return address 9 and call the subroutine at 10, which returns
+    // through the stack.
+    let program = [109, 20, 21101, 9, 0, 0, 1105, 1, 10, 99, 2105, 1, 0];
+    let analysis = Analysis::new(&program);
+    assert_eq!(
+        analysis.instrs.keys().copied().collect::<Vec<_>>(),
+        vec![0, 2, 6, 9, 10]
+    );
+    assert_eq!(
+        analysis.leaders.iter().copied().collect::<Vec<_>>(),
+        vec![0, 9, 10]
+    );
+    assert_eq!(analysis.code, vec![true; 13]);
+    assert!(!analysis.is_code(13));
+}
+
+#[test]
+fn test_transpile() {
+    let program = [3, 9, 1001, 9, 1, 9, 4, 9, 99, 0];
+    let source = transpile(&program, "crate::intcode");
+    assert!(source.contains("pub const PROGRAM: &[isize] = &[3, 9, 1001, 9, 1, 9, 4, 9, 99, 0];"));
+    assert!(source.contains("const CODE: &[u8] = &[1, 1, 1, 1, 1, 1, 1, 1, 1];"));
+    assert!(source.contains("                // 0002: ADD  [9], #1, [9]\n"));
+    assert!(source.contains("let value = add(memory.peek(9), 1, 2)?;"));
+    assert!(source.contains("return Ok(Some(StopReason::Halted));"));
+}
+
+#[test]
+fn test_compiled() {
+    struct QueueIoBus(Vec<isize>, Vec<isize>);
+
+    impl super::IoBus for QueueIoBus {
+        fn input(&mut self) -> Option<isize> {
+            self.0.pop()
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.1.push(i);
+            false
+        }
+    }
+
+    // Regenerate the module with `intaot` whenever the translation changes.
+    assert_eq!(
+        transpile(double::PROGRAM, "crate::intcode"),
+        include_str!("aot/double.rs")
+    );
+
+    let mut interpreted = (double::PROGRAM.to_vec(), QueueIoBus(vec![3], vec![]));
+    let mut compiled = (double::PROGRAM.to_vec(), QueueIoBus(vec![3], vec![]));
+    let mut registers = (0, 0);
+    for inputs in &[vec![0, 5], vec![]] {
+        let expected = super::run(&mut interpreted.0, registers, &mut interpreted.1);
+        // The doubling subroutine writes no code, so only the first run checks it.
+        let res = match registers {
+            (0, 0) => double::run(&mut compiled.0, registers, &mut compiled.1),
+            _ => double::resume(&mut compiled.0, registers, &mut compiled.1),
+        };
+        assert_eq!(res, expected);
+        assert_eq!(compiled.0, interpreted.0);
+        assert_eq!(compiled.1 .1, interpreted.1 .1);
+
+        registers = (res.pc, res.relative_base);
+        interpreted.1 .0.extend(inputs);
+        compiled.1 .0.extend(inputs);
+    }
+    assert_eq!(interpreted.1 .1, vec![6, 10]);
+}
+
+#[test]
+#[ignore = "timing; run with `cargo test --release -- --ignored --nocapture`"]
+fn test_speed() {
+    use std::time::Instant;
+
+    struct OnceIoBus(Option<isize>, Option<isize>);
+
+    impl super::IoBus for OnceIoBus {
+        fn input(&mut self) -> Option<isize> {
+            self.0.take()
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.1 = Some(i);
+            false
+        }
+    }
+
+    // Sums up every number from the input down to 1.
+    let n = 100_000_000;
+    assert_eq!(
+        transpile(sum::PROGRAM, "crate::intcode"),
+        include_str!("aot/sum.rs")
+    );
+
+    let start = Instant::now();
+    let mut interpreted = OnceIoBus(Some(n), None);
+    let expected = super::run(&mut sum::PROGRAM.to_vec(), (0, 0), &mut interpreted);
+    let interpreter = start.elapsed();
+
+    let start = Instant::now();
+    let mut compiled = OnceIoBus(Some(n), None);
+    let res = sum::run(&mut sum::PROGRAM.to_vec(), (0, 0), &mut compiled);
+    let native = start.elapsed();
+
+    assert_eq!(res, expected);
+    assert_eq!(compiled.1, Some(n * (n + 1) / 2));
+    assert_eq!(interpreted.1, compiled.1);
+    println!("interpreted: {:?}, compiled: {:?}", interpreter, native);
+    assert!(native < interpreter);
+}
@@ -0,0 +1,184 @@
+//! Compiled ahead of time from an intcode program.
+#![allow(unused, arithmetic_overflow, clippy::all)]
+use crate::intcode::memory::{Memory, DEFAULT_MEMORY_LIMIT};
+use crate::intcode::{self as intcode, IntcodeError, IoBus, RunResult, StopReason};
+
+/// The program this module was compiled from.
+pub const PROGRAM: &[isize] = &[109, 28, 3, 27, 1006, 27, 19, 21101, 14, 0, 0, 1105, 1, 20, 4, 27, 1105, 1, 2, 99, 1002, 27, 2, 27, 2105, 1, 0, 0, 0];
+
+/// Whether each cell belongs to a compiled instruction.
+const CODE: &[u8] = &[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1];
+
+#[inline(always)]
+fn relative(rb: usize, offset: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    let address = (rb as isize)
+        .checked_add(offset)
+        .ok_or(IntcodeError::AddressOverflow { pc, offset, param })?;
+    if address < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value: address, param });
+    }
+    Ok(address as usize)
+}
+
+#[inline(always)]
+fn add(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_add(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn mul(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_mul(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn target(value: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    if value < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value, param });
+    }
+    Ok(value as usize)
+}
+
+#[inline(always)]
+fn poke(
+    memory: &mut Vec<isize>,
+    address: usize,
+    value: isize,
+    pc: usize,
+    param: u32,
+) -> Result<(), IntcodeError> {
+    if !memory.poke(address, value, DEFAULT_MEMORY_LIMIT) {
+        return Err(IntcodeError::MemoryLimit { pc, address, param });
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn is_code(address: usize) -> bool {
+    CODE.get(address).copied().unwrap_or(0) != 0
+}
+
+/// Run the program like `intcode::run`, which takes over for anything not compiled.
+pub fn run(memory: &mut Vec<isize>, registers: (usize, usize), io: &mut impl IoBus) -> RunResult {
+    if (0..CODE.len()).any(|i| CODE[i] != 0 && memory.peek(i) != PROGRAM[i]) {
+        return intcode::run(memory, registers, io);
+    }
+    resume(memory, registers, io)
+}
+
+/// Continue a run like `run`, without checking the code against the program.
+///
+/// Nothing may have written into the code since `run` last checked it.
+pub fn resume(
+    memory: &mut Vec<isize>,
+    (mut pc, mut rb): (usize, usize),
+    io: &mut impl IoBus,
+) -> RunResult {
+    let mut n = 0;
+    let result = (|| -> Result<Option<StopReason>, IntcodeError> {
+        loop {
+            match pc {
+                0 => {
+                    // 0000: ARB  #28
+                    rb = relative(rb, 28, 0, 0)?;
+                    n += 1;
+                    pc = 2;
+                    continue;
+                }
+                2 => {
+                    // 0002: IN   [27]
+                    let address = 27;
+                    match io.input() {
+                        Some(value) => poke(memory, address, value, 2, 0)?,
+                        None => {
+                            pc = 2;
+                            return Ok(Some(StopReason::NeedsInput));
+                        }
+                    }
+                    n += 1;
+                    // 0004: JZ   [27], #19
+                    if memory.peek(27) == 0 {
+                        let target = 19;
+                        n += 1;
+                        pc = target;
+                        continue;
+                    }
+                    n += 1;
+                    pc = 7;
+                    continue;
+                }
+                7 => {
+                    // 0007: ADD  #14, #0, rb+0
+                    let value = add(14, 0, 7)?;
+                    let address = relative(rb, 0, 7, 2)?;
+                    poke(memory, address, value, 7, 2)?;
+                    n += 1;
+                    if is_code(address) {
+                        pc = 11;
+                        return Ok(None);
+                    }
+                    // 0011: JNZ  #1, #20
+                    let target = 20;
+                    n += 1;
+                    pc = target;
+                    continue;
+                }
+                14 => {
+                    // 0014: OUT  [27]
+                    let value = memory.peek(27);
+                    n += 1;
+                    if io.output(value) {
+                        pc = 16;
+                        return Ok(Some(StopReason::OutputPaused));
+                    }
+                    // 0016: JNZ  #1, #2
+                    let target = 2;
+                    n += 1;
+                    pc = target;
+                    continue;
+                }
+                19 => {
+                    // 0019: HLT
+                    n += 1;
+                    pc = 20;
+                    return Ok(Some(StopReason::Halted));
+                }
+                20 => {
+                    // 0020: MUL  [27], #2, [27]
+                    let value = mul(memory.peek(27), 2, 20)?;
+                    let address = 27;
+                    poke(memory, address, value, 20, 2)?;
+                    n += 1;
+                    // 0024: JNZ  #1, rb+0
+                    let target = target(memory.peek(relative(rb, 0, 24, 1)?), 24, 1)?;
+                    n += 1;
+                    pc = target;
+                    continue;
+                }
+                _ => return Ok(None),
+            }
+        }
+    })();
+
+    let stop = match result {
+        Ok(Some(stop)) => stop,
+        Ok(None) => {
+            let mut result = intcode::run(memory, (pc, rb), io);
+            result.instructions += n;
+            result.cost += n;
+            return result;
+        }
+        Err(e) => {
+            pc = e.pc();
+            StopReason::Error(e)
+        }
+    };
+    RunResult {
+        pc,
+        relative_base: rb,
+        stop,
+        instructions: n,
+        cost: n,
+    }
+}
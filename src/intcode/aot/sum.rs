@@ -0,0 +1,156 @@
+//! Compiled ahead of time from an intcode program.
+#![allow(unused, arithmetic_overflow, clippy::all)]
+use crate::intcode::memory::{Memory, DEFAULT_MEMORY_LIMIT};
+use crate::intcode::{self as intcode, IntcodeError, IoBus, RunResult, StopReason};
+
+/// The program this module was compiled from.
+pub const PROGRAM: &[isize] = &[3, 16, 1, 16, 17, 17, 1001, 16, -1, 16, 1005, 16, 2, 4, 17, 99, 0, 0];
+
+/// Whether each cell belongs to a compiled instruction.
+const CODE: &[u8] = &[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1];
+
+#[inline(always)]
+fn relative(rb: usize, offset: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    let address = (rb as isize)
+        .checked_add(offset)
+        .ok_or(IntcodeError::AddressOverflow { pc, offset, param })?;
+    if address < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value: address, param });
+    }
+    Ok(address as usize)
+}
+
+#[inline(always)]
+fn add(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_add(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn mul(lhs: isize, rhs: isize, pc: usize) -> Result<isize, IntcodeError> {
+    lhs.checked_mul(rhs)
+        .ok_or(IntcodeError::Overflow { pc, lhs, rhs })
+}
+
+#[inline(always)]
+fn target(value: isize, pc: usize, param: u32) -> Result<usize, IntcodeError> {
+    if value < 0 {
+        return Err(IntcodeError::NegativeAddress { pc, value, param });
+    }
+    Ok(value as usize)
+}
+
+#[inline(always)]
+fn poke(
+    memory: &mut Vec<isize>,
+    address: usize,
+    value: isize,
+    pc: usize,
+    param: u32,
+) -> Result<(), IntcodeError> {
+    if !memory.poke(address, value, DEFAULT_MEMORY_LIMIT) {
+        return Err(IntcodeError::MemoryLimit { pc, address, param });
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn is_code(address: usize) -> bool {
+    CODE.get(address).copied().unwrap_or(0) != 0
+}
+
+/// Run the program like `intcode::run`, which takes over for anything not compiled.
+pub fn run(memory: &mut Vec<isize>, registers: (usize, usize), io: &mut impl IoBus) -> RunResult {
+    if (0..CODE.len()).any(|i| CODE[i] != 0 && memory.peek(i) != PROGRAM[i]) {
+        return intcode::run(memory, registers, io);
+    }
+    resume(memory, registers, io)
+}
+
+/// Continue a run like `run`, without checking the code against the program.
+///
+/// Nothing may have written into the code since `run` last checked it.
+pub fn resume(
+    memory: &mut Vec<isize>,
+    (mut pc, mut rb): (usize, usize),
+    io: &mut impl IoBus,
+) -> RunResult {
+    let mut n = 0;
+    let result = (|| -> Result<Option<StopReason>, IntcodeError> {
+        loop {
+            match pc {
+                0 => {
+                    // 0000: IN   [16]
+                    let address = 16;
+                    match io.input() {
+                        Some(value) => poke(memory, address, value, 0, 0)?,
+                        None => {
+                            pc = 0;
+                            return Ok(Some(StopReason::NeedsInput));
+                        }
+                    }
+                    n += 1;
+                    pc = 2;
+                    continue;
+                }
+                2 => {
+                    // 0002: ADD  [16], [17], [17]
+                    let value = add(memory.peek(16), memory.peek(17), 2)?;
+                    let address = 17;
+                    poke(memory, address, value, 2, 2)?;
+                    n += 1;
+                    // 0006: ADD  [16], #-1, [16]
+                    let value = add(memory.peek(16), (-1), 6)?;
+                    let address = 16;
+                    poke(memory, address, value, 6, 2)?;
+                    n += 1;
+                    // 0010: JNZ  [16], #2
+                    if memory.peek(16) != 0 {
+                        let target = 2;
+                        n += 1;
+                        pc = target;
+                        continue;
+                    }
+                    n += 1;
+                    pc = 13;
+                    continue;
+                }
+                13 => {
+                    // 0013: OUT  [17]
+                    let value = memory.peek(17);
+                    n += 1;
+                    if io.output(value) {
+                        pc = 15;
+                        return Ok(Some(StopReason::OutputPaused));
+                    }
+                    // 0015: HLT
+                    n += 1;
+                    pc = 16;
+                    return Ok(Some(StopReason::Halted));
+                }
+                _ => return Ok(None),
+            }
+        }
+    })();
+
+    let stop = match result {
+        Ok(Some(stop)) => stop,
+        Ok(None) => {
+            let mut result = intcode::run(memory, (pc, rb), io);
+            result.instructions += n;
+            result.cost += n;
+            return result;
+        }
+        Err(e) => {
+            pc = e.pc();
+            StopReason::Error(e)
+        }
+    };
+    RunResult {
+        pc,
+        relative_base: rb,
+        stop,
+        instructions: n,
+        cost: n,
+    }
+}
@@ -0,0 +1,117 @@
+//! # ascii - talking to programs in text
+//!
+//! An [`AsciiIoBus`] feeds text to a program one character code at a time,
+//! and collects the character codes it outputs into a `String`. Any output
+//! which is not an ASCII character, such as the answer of a program after a
+//! long explanation, is kept apart in [`AsciiIoBus::values`].
+use super::cell::Cell;
+use super::IoBus;
+use std::collections::VecDeque;
+
+/// A bus which reads and writes text; see the [module documentation](self).
+///
+/// The machine stops with [`StopReason::NeedsInput`](super::StopReason) once
+/// all text has been read, so more can be given before running it again.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AsciiIoBus<C = isize> {
+    input: VecDeque<char>,
+
+    /// The text output so far.
+    pub output: String,
+
+    /// The output values which are not ASCII characters, in order.
+    pub values: Vec<C>,
+}
+
+impl<C> AsciiIoBus<C> {
+    /// Create a bus without any input yet.
+    pub fn new() -> Self {
+        AsciiIoBus {
+            input: VecDeque::new(),
+            output: String::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Create a bus which gives the text as input.
+    pub fn with_input(text: &str) -> Self {
+        let mut bus = Self::new();
+        bus.push_str(text);
+        bus
+    }
+
+    /// Create a bus which gives every line as input, each followed by a
+    /// newline.
+    pub fn with_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut bus = Self::new();
+        for line in lines {
+            bus.push_line(line);
+        }
+        bus
+    }
+
+    /// Give the text as input after any text which has not been read yet.
+    pub fn push_str(&mut self, text: &str) {
+        self.input.extend(text.chars());
+    }
+
+    /// Give the line as input, followed by a newline.
+    pub fn push_line(&mut self, line: &str) {
+        self.push_str(line);
+        self.input.push_back('\n');
+    }
+
+    /// Whether all input has been read.
+    pub fn input_is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Take the text output so far, leaving nothing behind.
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl<C: Cell> IoBus<C> for AsciiIoBus<C> {
+    fn input(&mut self) -> Option<C> {
+        self.input.pop_front().map(|c| C::from_isize(c as isize))
+    }
+
+    fn output(&mut self, i: C) -> bool {
+        match i.to_isize() {
+            Some(code @ 0..=127) => self.output.push(code as u8 as char),
+            _ => self.values.push(i),
+        }
+        false
+    }
+}
+
+#[test]
+fn test_echo() {
+    use super::{run, StopReason};
+
+    // Output every input plus one, until the input runs out.
+    let mut program: Vec<isize> = vec![3, 9, 1001, 9, 1, 9, 4, 9, 1105, 1, 0];
+    let mut bus = AsciiIoBus::with_lines(vec!["HAL", "9000"]);
+    let res = run(&mut program, (0, 0), &mut bus);
+    assert_eq!(res.stop, StopReason::NeedsInput);
+    assert!(bus.input_is_empty());
+    assert_eq!(bus.take_output(), "IBM\u{b}:111\u{b}");
+
+    bus.push_str("~\u{e9}");
+    let res = run(&mut program, (res.pc, res.relative_base), &mut bus);
+    assert_eq!(res.stop, StopReason::NeedsInput);
+    assert_eq!(bus.output, "\u{7f}");
+    assert_eq!(bus.values, vec![0xea]);
+}
+
+#[test]
+fn test_values() {
+    use super::Machine;
+
+    let mut machine = Machine::with_memory(vec![104i64, 72, 104, 105, 104, -1, 104, 1_000_000, 99]);
+    let mut bus = AsciiIoBus::new();
+    assert!(machine.run(&mut bus).has_halted());
+    assert_eq!(bus.output, "Hi");
+    assert_eq!(bus.values, vec![-1, 1_000_000]);
+}
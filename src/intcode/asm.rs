@@ -0,0 +1,505 @@
+//! # asm - writing intcode programs by hand
+//!
+//! The assembler reads the same format the [disassembler](`super::disasm`)
+//! writes, so a listing can be assembled back into its program. Every line
+//! holds at most one item, and anything after a `;` is a comment:
+//!
+//! ```text
+//! ; Count down from 3, outputting every number.
+//!         ARB  #counter
+//! loop:   OUT  rb+0
+//!         ADD  rb+0, #-1, rb+0
+//!         JNZ  rb+0, #loop
+//!         HLT
+//! counter:
+//!         .data 3
+//! ```
+//!
+//! A line may start with a label (`name:`), which is the address of the next
+//! item, or with an address (`0012:`), which must be the address of the item
+//! on that line.
+//!
+//! Instructions are written with the mnemonics of the disassembler, and their
+//! parameters as `#expr` in immediate mode, `[expr]` in position mode, and
+//! `rb+expr` or `rb-expr` in relative mode. The `.data` directive places its
+//! comma-separated values into the program as is.
+//!
+//! An expression is a sum of integers and labels, such as `label+1` or
+//! `end-start`. Labels may be used before they are defined.
+use super::{Instr, Mod};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An error in the source of a program, along with the 1-indexed `line` it is
+/// found on.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum AsmError {
+    /// There is no instruction or directive called `mnemonic`.
+    UnknownMnemonic { line: usize, mnemonic: String },
+
+    /// The instruction takes `expected` parameters, but `found` were given.
+    OperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+
+    /// The parameter is not written in any known mode.
+    InvalidOperand { line: usize, operand: String },
+
+    /// The expression is neither a number, a label, nor a sum of those.
+    InvalidExpression { line: usize, expr: String },
+
+    /// The label is used, but never defined.
+    UnknownLabel { line: usize, label: String },
+
+    /// The label is defined more than once.
+    DuplicateLabel { line: usize, label: String },
+
+    /// The line claims to be at `found`, but is actually at `expected`.
+    AddressMismatch {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic `{}`", line, mnemonic)
+            }
+            Self::OperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} parameters, found {}",
+                line, expected, found
+            ),
+            Self::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid parameter `{}`", line, operand)
+            }
+            Self::InvalidExpression { line, expr } => {
+                write!(f, "line {}: invalid expression `{}`", line, expr)
+            }
+            Self::UnknownLabel { line, label } => {
+                write!(f, "line {}: unknown label `{}`", line, label)
+            }
+            Self::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label `{}` is already defined", line, label)
+            }
+            Self::AddressMismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: address {} given, but the line is at {}",
+                line, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A parsed line of source, before any labels are resolved.
+struct Item<'a> {
+    line: usize,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>,
+}
+
+impl Item<'_> {
+    /// The amount of cells this item assembles into.
+    fn size(&self) -> Result<usize, AsmError> {
+        if self.mnemonic == ".data" {
+            return Ok(self.operands.len());
+        }
+
+        let expected = match operand_count(self.mnemonic) {
+            Some(n) => n,
+            None => {
+                return Err(AsmError::UnknownMnemonic {
+                    line: self.line,
+                    mnemonic: self.mnemonic.to_owned(),
+                })
+            }
+        };
+        if expected != self.operands.len() {
+            return Err(AsmError::OperandCount {
+                line: self.line,
+                expected,
+                found: self.operands.len(),
+            });
+        }
+
+        Ok(1 + expected)
+    }
+}
+
+fn operand_count(mnemonic: &str) -> Option<usize> {
+    Some(match mnemonic {
+        "ADD" | "MUL" | "LT" | "EQ" => 3,
+        "JNZ" | "JZ" => 2,
+        "IN" | "OUT" | "ARB" => 1,
+        "HLT" => 0,
+        _ => return None,
+    })
+}
+
+/// Assemble a program from its source.
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    let mut items = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = 0;
+
+    // The first pass lays out the program, so every label is known before any
+    // expression is evaluated.
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let mut text = text.split(';').next().unwrap_or("").trim();
+
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if name.bytes().all(|b| b.is_ascii_digit()) && !name.is_empty() {
+                let found = name.parse().map_err(|_| AsmError::InvalidExpression {
+                    line,
+                    expr: name.to_owned(),
+                })?;
+                if found != address {
+                    return Err(AsmError::AddressMismatch {
+                        line,
+                        expected: address,
+                        found,
+                    });
+                }
+            } else if is_label(name) {
+                if labels.insert(name, address).is_some() {
+                    return Err(AsmError::DuplicateLabel {
+                        line,
+                        label: name.to_owned(),
+                    });
+                }
+            } else {
+                break;
+            }
+            text = text[colon + 1..].trim_start();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, ""),
+        };
+        let operands = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+
+        // Mnemonics are case insensitive, so keep their canonical form around.
+        let mnemonic = if mnemonic.eq_ignore_ascii_case(".data") {
+            ".data"
+        } else {
+            MNEMONICS
+                .iter()
+                .find(|m| m.eq_ignore_ascii_case(mnemonic))
+                .copied()
+                .unwrap_or(mnemonic)
+        };
+        let item = Item {
+            line,
+            mnemonic,
+            operands,
+        };
+        address += item.size()?;
+        items.push(item);
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for item in items {
+        let line = item.line;
+        if item.mnemonic == ".data" {
+            for operand in &item.operands {
+                program.push(evaluate(operand, &labels, line)?);
+            }
+            continue;
+        }
+
+        let mut params = Vec::with_capacity(item.operands.len());
+        for operand in &item.operands {
+            params.push(parse_operand(operand, &labels, line)?);
+        }
+        let instr = match (item.mnemonic, params.as_slice()) {
+            ("ADD", &[a, b, c]) => Instr::Add(a, b, c),
+            ("MUL", &[a, b, c]) => Instr::Mul(a, b, c),
+            ("IN", &[a]) => Instr::Input(a),
+            ("OUT", &[a]) => Instr::Output(a),
+            ("JNZ", &[a, b]) => Instr::JNZ(a, b),
+            ("JZ", &[a, b]) => Instr::JZ(a, b),
+            ("LT", &[a, b, c]) => Instr::LT(a, b, c),
+            ("EQ", &[a, b, c]) => Instr::EQ(a, b, c),
+            ("ARB", &[a]) => Instr::ModRelBas(a),
+            ("HLT", &[]) => Instr::Hlt,
+            _ => unreachable!("operand counts are checked while laying out"),
+        };
+        program.extend(instr.encode());
+    }
+
+    Ok(program)
+}
+
+const MNEMONICS: [&str; 10] = [
+    "ADD", "MUL", "IN", "OUT", "JNZ", "JZ", "LT", "EQ", "ARB", "HLT",
+];
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_operand(
+    operand: &str,
+    labels: &HashMap<&str, usize>,
+    line: usize,
+) -> Result<Mod, AsmError> {
+    let invalid = || AsmError::InvalidOperand {
+        line,
+        operand: operand.to_owned(),
+    };
+
+    if let Some(expr) = operand.strip_prefix('#') {
+        return Ok(Mod::Immediate(evaluate(expr, labels, line)?));
+    }
+
+    if let Some(expr) = operand.strip_prefix('[').and_then(|o| o.strip_suffix(']')) {
+        let idx = evaluate(expr, labels, line)?;
+        if idx < 0 {
+            return Err(invalid());
+        }
+        return Ok(Mod::Position(idx as usize));
+    }
+
+    if let Some(expr) = operand.strip_prefix("rb") {
+        let expr = expr.trim_start();
+        if expr.is_empty() {
+            return Ok(Mod::Relative(0));
+        }
+        if expr.starts_with('+') || expr.starts_with('-') {
+            // The sign is part of the expression, so `rb-a+b` is `rb+(-a+b)`.
+            return Ok(Mod::Relative(evaluate(expr, labels, line)?));
+        }
+    }
+
+    Err(invalid())
+}
+
+/// Evaluate a sum of integers and labels.
+fn evaluate(expr: &str, labels: &HashMap<&str, usize>, line: usize) -> Result<isize, AsmError> {
+    let invalid = || AsmError::InvalidExpression {
+        line,
+        expr: expr.to_owned(),
+    };
+
+    let mut total: isize = 0;
+    let mut rest = expr.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut negative = false;
+    if let Some(r) = rest.strip_prefix('+') {
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('-') {
+        negative = true;
+        rest = r;
+    }
+
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        // Parse a number along with its sign, as `isize::MIN` has no positive
+        // counterpart.
+        let literal = if negative {
+            format!("-{}", term)
+        } else {
+            term.to_owned()
+        };
+        let sum = if let Ok(n) = literal.parse::<isize>() {
+            total.checked_add(n)
+        } else if is_label(term) {
+            let address = match labels.get(term) {
+                Some(&address) => address as isize,
+                None => {
+                    return Err(AsmError::UnknownLabel {
+                        line,
+                        label: term.to_owned(),
+                    })
+                }
+            };
+            if negative {
+                total.checked_sub(address)
+            } else {
+                total.checked_add(address)
+            }
+        } else {
+            return Err(invalid());
+        };
+        total = sum.ok_or_else(invalid)?;
+
+        if end == rest.len() {
+            return Ok(total);
+        }
+        negative = rest.as_bytes()[end] == b'-';
+        rest = &rest[end + 1..];
+    }
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        ; Count down from 3, outputting every number.
+                ARB  #counter
+        loop:   OUT  rb+0
+                ADD  rb+0, #-1, rb+0
+                JNZ  rb+0, #loop
+                HLT
+        counter:
+                .data 3
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(
+        program,
+        vec![109, 12, 204, 0, 21201, 0, -1, 0, 1205, 0, 2, 99, 3]
+    );
+
+    struct CollectIoBus(Vec<isize>);
+
+    impl super::IoBus for CollectIoBus {
+        fn input(&mut self) -> Option<isize> {
+            panic!("No input allowed")
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    let mut bus = CollectIoBus(Vec::new());
+    let res = super::Machine::new(program).run(&mut bus);
+    assert!(res.has_halted());
+    assert_eq!(bus.0, vec![3, 2, 1]);
+}
+
+#[test]
+fn test_expressions() {
+    let source = "
+        start: mul [end-1], #end - start + 2, rb-end
+               .data end, start+1, -3
+        end:
+    ";
+    assert_eq!(assemble(source), Ok(vec![21002, 6, 9, -7, 7, 1, -3]));
+
+    let source = "
+        .data -9223372036854775808, 9223372036854775807 - 1, -9223372036854775807 - 1
+    ";
+    assert_eq!(
+        assemble(source),
+        Ok(vec![isize::MIN, isize::MAX - 1, isize::MIN])
+    );
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        assemble("nop"),
+        Err(AsmError::UnknownMnemonic {
+            line: 1,
+            mnemonic: "nop".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("HLT\nADD #1, #2"),
+        Err(AsmError::OperandCount {
+            line: 2,
+            expected: 3,
+            found: 2
+        })
+    );
+    assert_eq!(
+        assemble("OUT 5"),
+        Err(AsmError::InvalidOperand {
+            line: 1,
+            operand: "5".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("OUT [-1]"),
+        Err(AsmError::InvalidOperand {
+            line: 1,
+            operand: "[-1]".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("OUT #1+"),
+        Err(AsmError::InvalidExpression {
+            line: 1,
+            expr: "1+".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("OUT #nowhere"),
+        Err(AsmError::UnknownLabel {
+            line: 1,
+            label: "nowhere".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("a: HLT\na: HLT"),
+        Err(AsmError::DuplicateLabel {
+            line: 2,
+            label: "a".to_owned()
+        })
+    );
+    assert_eq!(
+        assemble("0000: HLT\n0002: HLT"),
+        Err(AsmError::AddressMismatch {
+            line: 2,
+            expected: 1,
+            found: 2
+        })
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    let programs: &[&[isize]] = &[
+        &[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50],
+        &[109, -1, 21101, 7, 8, 3, 1005, 3, 0, 4, 3, 99, -5],
+        &[
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ],
+        &[1099, 1, 0, 203, -4],
+        &[1101, isize::MIN, isize::MAX, 7, 99, isize::MIN, isize::MAX],
+    ];
+
+    for &program in programs {
+        let listing = super::disasm::listing(program);
+        assert_eq!(assemble(&listing).as_deref(), Ok(program), "{}", listing);
+    }
+}
@@ -0,0 +1,164 @@
+//! # cache - instructions which have already been decoded
+//!
+//! Decoding an instruction takes a division for every parameter mode, while
+//! most programs spend their time in a few loops. The machine therefore keeps
+//! the op code and modes of every instruction it decodes, indexed by its
+//! address, and drops them again once a write hits any of its cells, so
+//! self-modifying code still sees the instruction it wrote. The operands are
+//! read from memory again, which keeps the cache at two bytes per cell.
+use super::cell::Cell;
+use super::memory::Memory;
+use super::{Instr, Mod};
+use std::num::NonZeroU16;
+
+/// The longest instruction, whose cells a single write may hit.
+const MAX_INSTR_SIZE: usize = 4;
+
+/// The decoded instructions of a machine.
+///
+/// The cache is no part of the state of a machine: it compares equal to any
+/// other cache, so two machines which differ only in what they have decoded
+/// are still equal.
+#[derive(Clone, Default)]
+pub struct DecodeCache {
+    instrs: Vec<Option<Packed>>,
+}
+
+/// A decoded instruction without its operands: the op code in the low byte,
+/// and the mode of every parameter in two bits above it.
+#[derive(Clone, Copy)]
+struct Packed(NonZeroU16);
+
+impl Packed {
+    #[inline(always)]
+    fn new(instr: Instr) -> Self {
+        let mut packed = instr.opcode() as u16;
+        for (idx, param) in instr.params().iter().enumerate() {
+            let mode = match param {
+                Some(Mod::Position(_)) | None => 0,
+                Some(Mod::Immediate(_)) => 1,
+                Some(Mod::Relative(_)) => 2,
+            };
+            packed |= mode << (8 + 2 * idx);
+        }
+        Packed(NonZeroU16::new(packed).expect("op codes are never zero"))
+    }
+
+    /// Rebuild the instruction at `pc` from the operands in `memory`.
+    #[inline(always)]
+    fn unpack(self, memory: &(impl Memory + ?Sized), pc: usize) -> Instr {
+        let packed = self.0.get();
+        let param = |idx: usize| {
+            let value = memory.peek(pc + idx + 1).to_isize_saturating();
+            match (packed >> (8 + 2 * idx)) & 3 {
+                0 => Mod::Position(value as usize),
+                1 => Mod::Immediate(value),
+                _ => Mod::Relative(value),
+            }
+        };
+
+        match packed & 0xff {
+            1 => Instr::Add(param(0), param(1), param(2)),
+            2 => Instr::Mul(param(0), param(1), param(2)),
+            3 => Instr::Input(param(0)),
+            4 => Instr::Output(param(0)),
+            5 => Instr::JNZ(param(0), param(1)),
+            6 => Instr::JZ(param(0), param(1)),
+            7 => Instr::LT(param(0), param(1), param(2)),
+            8 => Instr::EQ(param(0), param(1), param(2)),
+            9 => Instr::ModRelBas(param(0)),
+            _ => Instr::Hlt,
+        }
+    }
+}
+
+impl DecodeCache {
+    /// The instruction decoded at `pc` of `memory`, if it is still valid.
+    #[inline(always)]
+    pub fn get(&self, memory: &(impl Memory + ?Sized), pc: usize) -> Option<Instr> {
+        let packed = self.instrs.get(pc).copied().flatten()?;
+        Some(packed.unpack(memory, pc))
+    }
+
+    /// Keep the instruction decoded at `pc`.
+    ///
+    /// Only addresses below `bound` are kept, so a program executing far away
+    /// from its cells doesn't grow the cache without limit.
+    #[inline(always)]
+    pub fn insert(&mut self, pc: usize, instr: Instr, bound: usize) {
+        if pc >= bound {
+            return;
+        }
+
+        if pc >= self.instrs.len() {
+            self.instrs.resize(pc + 1, None);
+        }
+        self.instrs[pc] = Some(Packed::new(instr));
+    }
+
+    /// Drop every instruction with a cell at `address`.
+    #[inline(always)]
+    pub fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(MAX_INSTR_SIZE - 1);
+        let end = (address + 1).min(self.instrs.len());
+        for instr in self.instrs.iter_mut().take(end).skip(start) {
+            *instr = None;
+        }
+    }
+
+    /// Drop every instruction, e.g. after the memory was changed from outside
+    /// the machine.
+    pub fn clear(&mut self) {
+        self.instrs.clear();
+    }
+}
+
+impl PartialEq for DecodeCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DecodeCache {}
+
+impl std::fmt::Debug for DecodeCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let decoded = self.instrs.iter().filter(|instr| instr.is_some()).count();
+        write!(f, "DecodeCache({} decoded)", decoded)
+    }
+}
+
+#[test]
+fn test_invalidate() {
+    let memory: Vec<isize> = vec![99; 8];
+    let mut cache = DecodeCache::default();
+    for pc in 0..8 {
+        cache.insert(pc, Instr::Hlt, 6);
+    }
+    assert_eq!(cache.get(&memory, 5), Some(Instr::Hlt));
+    assert_eq!(cache.get(&memory, 6), None);
+
+    cache.invalidate(4);
+    let kept: Vec<_> = (0..6).map(|pc| cache.get(&memory, pc).is_some()).collect();
+    assert_eq!(kept, vec![true, false, false, false, false, true]);
+
+    cache.invalidate(100);
+    cache.invalidate(0);
+    assert_eq!(cache.get(&memory, 0), None);
+    assert_eq!(cache.get(&memory, 5), Some(Instr::Hlt));
+}
+
+#[test]
+fn test_packed() {
+    assert_eq!(std::mem::size_of::<Option<Packed>>(), 2);
+
+    let program: Vec<isize> = vec![1201, -3, 7, 4, 21107, 5, 6, -1, 99];
+    let mut cache = DecodeCache::default();
+    let mut pc = 0;
+    while pc < program.len() {
+        let instr = Instr::decode(&program, pc).unwrap();
+        cache.insert(pc, instr, program.len());
+        assert_eq!(cache.get(&program, pc), Some(instr));
+        pc += instr.size();
+    }
+}
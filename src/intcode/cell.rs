@@ -0,0 +1,386 @@
+//! # cell - the values a machine computes with
+//!
+//! Every cell of the memory, every input and every output is a [`Cell`]. The
+//! machine is generic over it, so a program can be run with `isize` (the
+//! default, whose size depends on the target), `i64` or `i128` cells, or
+//! with [`BigInt`] cells which never overflow at all.
+//!
+//! What happens when `Add` or `Mul` overflow a cell is up to the
+//! [`Overflow`](`super::Overflow`) of the machine.
+//!
+//! Instructions are decoded the same way whatever the cell: op codes,
+//! addresses and relative offsets have to fit an `isize`. Only the values the
+//! program computes with may be larger.
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// This defines a value a machine may hold in its cells.
+///
+/// The [`Default`] value of a cell is `0`.
+pub trait Cell: Clone + Default + Eq + Ord + Hash + fmt::Debug + fmt::Display + FromStr {
+    fn from_isize(value: isize) -> Self;
+
+    /// The value as an `isize`, or `None` if it doesn't fit.
+    fn to_isize(&self) -> Option<isize>;
+
+    /// The value as an `isize`, saturated to `isize::MIN` or `isize::MAX` if
+    /// it doesn't fit.
+    #[inline(always)]
+    fn to_isize_saturating(&self) -> isize {
+        self.to_isize().unwrap_or_else(|| {
+            if *self < Self::default() {
+                isize::MIN
+            } else {
+                isize::MAX
+            }
+        })
+    }
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+
+    fn wrapping_add(&self, rhs: &Self) -> Self;
+
+    fn saturating_add(&self, rhs: &Self) -> Self;
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+
+    fn wrapping_mul(&self, rhs: &Self) -> Self;
+
+    fn saturating_mul(&self, rhs: &Self) -> Self;
+}
+
+macro_rules! primitive_cell_impl {
+    ($($types:ty),*) => {
+        $(
+            impl Cell for $types {
+                #[inline(always)]
+                fn from_isize(value: isize) -> Self {
+                    value as Self
+                }
+
+                #[inline(always)]
+                fn to_isize(&self) -> Option<isize> {
+                    use std::convert::TryFrom as _;
+                    isize::try_from(*self).ok()
+                }
+
+                #[inline(always)]
+                fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                    <$types>::checked_add(*self, *rhs)
+                }
+
+                #[inline(always)]
+                fn wrapping_add(&self, rhs: &Self) -> Self {
+                    <$types>::wrapping_add(*self, *rhs)
+                }
+
+                #[inline(always)]
+                fn saturating_add(&self, rhs: &Self) -> Self {
+                    <$types>::saturating_add(*self, *rhs)
+                }
+
+                #[inline(always)]
+                fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                    <$types>::checked_mul(*self, *rhs)
+                }
+
+                #[inline(always)]
+                fn wrapping_mul(&self, rhs: &Self) -> Self {
+                    <$types>::wrapping_mul(*self, *rhs)
+                }
+
+                #[inline(always)]
+                fn saturating_mul(&self, rhs: &Self) -> Self {
+                    <$types>::saturating_mul(*self, *rhs)
+                }
+            }
+        )*
+    };
+}
+
+primitive_cell_impl!(isize, i64, i128);
+
+/// The base of the limbs of a [`BigInt`].
+const BASE: u64 = 1_000_000_000;
+
+/// The amount of decimal digits in a limb of a [`BigInt`].
+const BASE_DIGITS: usize = 9;
+
+/// An integer of arbitrary precision.
+///
+/// The magnitude is kept in limbs of base `10^9`, least significant first,
+/// so it is cheap to read from and write to decimal text.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    /// Drop leading zero limbs, and the sign of zero.
+    fn normalize(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        if self.limbs.is_empty() {
+            self.negative = false;
+        }
+        self
+    }
+
+    fn cmp_magnitude(lhs: &[u32], rhs: &[u32]) -> Ordering {
+        lhs.len()
+            .cmp(&rhs.len())
+            .then_with(|| lhs.iter().rev().cmp(rhs.iter().rev()))
+    }
+
+    fn add_magnitude(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+        let mut limbs = Vec::with_capacity(lhs.len().max(rhs.len()) + 1);
+        let mut carry = 0;
+        for i in 0..lhs.len().max(rhs.len()) {
+            let sum = carry
+                + lhs.get(i).copied().unwrap_or(0) as u64
+                + rhs.get(i).copied().unwrap_or(0) as u64;
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry != 0 {
+            limbs.push(carry as u32);
+        }
+        limbs
+    }
+
+    /// Subtract the magnitudes, where `lhs` must not be smaller than `rhs`.
+    fn sub_magnitude(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+        let mut limbs = Vec::with_capacity(lhs.len());
+        let mut borrow = 0;
+        for (i, &limb) in lhs.iter().enumerate() {
+            let sub = borrow + rhs.get(i).copied().unwrap_or(0) as u64;
+            let (limb, next) = if limb as u64 >= sub {
+                (limb as u64 - sub, 0)
+            } else {
+                (limb as u64 + BASE - sub, 1)
+            };
+            limbs.push(limb as u32);
+            borrow = next;
+        }
+        limbs
+    }
+}
+
+impl Cell for BigInt {
+    fn from_isize(value: isize) -> Self {
+        let mut magnitude = value.unsigned_abs() as u64;
+        let mut limbs = Vec::new();
+        while magnitude != 0 {
+            limbs.push((magnitude % BASE) as u32);
+            magnitude /= BASE;
+        }
+        BigInt {
+            negative: value < 0,
+            limbs,
+        }
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        let mut magnitude: i128 = 0;
+        for &limb in self.limbs.iter().rev() {
+            magnitude = magnitude
+                .checked_mul(BASE as i128)?
+                .checked_add(limb as i128)?;
+        }
+        let value = if self.negative { -magnitude } else { magnitude };
+        if value < isize::MIN as i128 || value > isize::MAX as i128 {
+            return None;
+        }
+        Some(value as isize)
+    }
+
+    /// A `BigInt` never overflows, so this always succeeds.
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self.add(rhs))
+    }
+
+    fn wrapping_add(&self, rhs: &Self) -> Self {
+        self.add(rhs)
+    }
+
+    fn saturating_add(&self, rhs: &Self) -> Self {
+        self.add(rhs)
+    }
+
+    /// A `BigInt` never overflows, so this always succeeds.
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self.mul(rhs))
+    }
+
+    fn wrapping_mul(&self, rhs: &Self) -> Self {
+        self.mul(rhs)
+    }
+
+    fn saturating_mul(&self, rhs: &Self) -> Self {
+        self.mul(rhs)
+    }
+}
+
+impl BigInt {
+    fn add(&self, rhs: &Self) -> Self {
+        if self.negative == rhs.negative {
+            return BigInt {
+                negative: self.negative,
+                limbs: Self::add_magnitude(&self.limbs, &rhs.limbs),
+            };
+        }
+
+        let (larger, smaller) = match Self::cmp_magnitude(&self.limbs, &rhs.limbs) {
+            Ordering::Less => (rhs, self),
+            _ => (self, rhs),
+        };
+        BigInt {
+            negative: larger.negative,
+            limbs: Self::sub_magnitude(&larger.limbs, &smaller.limbs),
+        }
+        .normalize()
+    }
+
+    fn mul(&self, rhs: &Self) -> Self {
+        if self.limbs.is_empty() || rhs.limbs.is_empty() {
+            return BigInt::default();
+        }
+
+        let mut product = vec![0u64; self.limbs.len() + rhs.limbs.len()];
+        for (i, &lhs) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &rhs) in rhs.limbs.iter().enumerate() {
+                let cell = product[i + j] + lhs as u64 * rhs as u64 + carry;
+                product[i + j] = cell % BASE;
+                carry = cell / BASE;
+            }
+            product[i + rhs.limbs.len()] += carry;
+        }
+
+        BigInt {
+            negative: self.negative != rhs.negative,
+            limbs: product.into_iter().map(|limb| limb as u32).collect(),
+        }
+        .normalize()
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => Self::cmp_magnitude(&self.limbs, &other.limbs),
+            (true, true) => Self::cmp_magnitude(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut limbs = self.limbs.iter().rev();
+        let top = match limbs.next() {
+            Some(top) => top,
+            None => return f.write_str("0"),
+        };
+
+        if self.negative {
+            f.write_str("-")?;
+        }
+        write!(f, "{}", top)?;
+        for limb in limbs {
+            write!(f, "{:0width$}", limb, width = BASE_DIGITS)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error raised when parsing a [`BigInt`] from text which isn't a decimal
+/// integer.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct ParseBigIntError;
+
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid integer")
+    }
+}
+
+impl std::error::Error for ParseBigIntError {}
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+
+        let limbs = digits
+            .as_bytes()
+            .rchunks(BASE_DIGITS)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0, |limb, &digit| limb * 10 + (digit - b'0') as u32)
+            })
+            .collect();
+        Ok(BigInt { negative, limbs }.normalize())
+    }
+}
+
+#[test]
+fn test_primitive() {
+    assert_eq!(Cell::checked_mul(&i64::from_isize(-3), &7), Some(-21));
+    assert_eq!(
+        Cell::checked_mul(&34915192i64, &34915192),
+        Some(1219070632396864)
+    );
+    assert_eq!(Cell::checked_add(&i64::MAX, &1), None);
+    assert_eq!(Cell::wrapping_add(&i64::MAX, &1), i64::MIN);
+    assert_eq!(Cell::saturating_mul(&i64::MIN, &2), i64::MIN);
+    assert_eq!(i128::MAX.to_isize(), None);
+    assert_eq!(i128::MAX.to_isize_saturating(), isize::MAX);
+    assert_eq!(i128::MIN.to_isize_saturating(), isize::MIN);
+}
+
+#[test]
+fn test_big_int() {
+    let big = |s: &str| s.parse::<BigInt>().unwrap();
+
+    assert_eq!(big("-0"), BigInt::default());
+    assert_eq!(big("000123").to_string(), "123");
+    assert_eq!(big("-1000000000").to_string(), "-1000000000");
+    assert_eq!("12a".parse::<BigInt>(), Err(ParseBigIntError));
+    assert_eq!("-".parse::<BigInt>(), Err(ParseBigIntError));
+
+    let huge = big("1125899906842624").mul(&big("1125899906842624"));
+    assert_eq!(huge.to_string(), "1267650600228229401496703205376");
+    assert_eq!(huge.to_isize(), None);
+    assert_eq!(huge.to_isize_saturating(), isize::MAX);
+
+    assert_eq!(big("1000000000").add(&big("-1")).to_string(), "999999999");
+    assert_eq!(big("-5").add(&big("3")), BigInt::from_isize(-2));
+    assert_eq!(big("5").add(&big("-5")), BigInt::default());
+    assert_eq!(big("-7").mul(&big("6")), BigInt::from_isize(-42));
+    assert_eq!(BigInt::from_isize(isize::MIN).to_isize(), Some(isize::MIN));
+
+    assert!(big("-10") < big("-9"));
+    assert!(big("-1") < BigInt::default());
+    assert!(big("1000000000") > big("999999999"));
+}
@@ -0,0 +1,303 @@
+//! # cfg - the control flow of intcode programs
+//!
+//! The control-flow graph is recovered statically, starting at address `0`
+//! and any other entries given to [`Cfg::with_entries`].
+//! A block runs up to the first jump or halt, or up to where another block
+//! starts, and is left along its edges:
+//!
+//! * a jump edge to the target of a `JNZ` or `JZ` in immediate mode;
+//! * an unresolved edge for a jump whose target is only known at runtime;
+//! * a fall-through edge to the very next instruction.
+//!
+//! A jump whose condition is an immediate value is either always or never
+//! taken, so it only gets the one edge it will actually follow, e.g. the
+//! `JNZ #1, #target` programs use to jump unconditionally.
+//!
+//! [`Cfg::to_dot`] writes the graph for Graphviz, e.g. `dot -Tsvg`.
+use super::disasm::{self, Line};
+use super::{Instr, Mod};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+/// A run of instructions which is only ever entered at its start.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub start: usize,
+
+    /// The instructions of the block, along with their addresses.
+    pub instrs: Vec<(usize, Instr)>,
+}
+
+impl Block {
+    /// The address right after the last instruction of the block.
+    pub fn end(&self) -> usize {
+        match self.instrs.last() {
+            Some(&(address, instr)) => address + instr.size(),
+            None => self.start,
+        }
+    }
+}
+
+/// How control leaves a block.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum Edge {
+    /// A jump to a fixed address.
+    Jump { from: usize, to: usize },
+
+    /// A jump to an address which is only known at runtime.
+    Unresolved { from: usize },
+
+    /// Continuing with the next instruction.
+    FallThrough { from: usize, to: usize },
+}
+
+impl Edge {
+    /// The start of the block this edge leaves.
+    pub fn from(&self) -> usize {
+        match *self {
+            Self::Jump { from, .. }
+            | Self::Unresolved { from }
+            | Self::FallThrough { from, .. } => from,
+        }
+    }
+
+    /// The address this edge goes to, if it is known.
+    pub fn to(&self) -> Option<usize> {
+        match *self {
+            Self::Jump { to, .. } | Self::FallThrough { to, .. } => Some(to),
+            Self::Unresolved { .. } => None,
+        }
+    }
+}
+
+/// The control-flow graph of a program; see the [module
+/// documentation](self).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cfg {
+    /// Every reachable block, by the address it starts at.
+    pub blocks: BTreeMap<usize, Block>,
+
+    /// Every edge, ordered by the block it leaves.
+    pub edges: Vec<Edge>,
+}
+
+/// Whether a jump is taken, if that is known before the program runs.
+#[inline(always)]
+fn taken(instr: Instr) -> Option<bool> {
+    match instr {
+        Instr::JNZ(Mod::Immediate(cond), _) => Some(cond != 0),
+        Instr::JZ(Mod::Immediate(cond), _) => Some(cond == 0),
+        _ => None,
+    }
+}
+
+impl Cfg {
+    /// Recover the graph of the program.
+    pub fn new(program: &[isize]) -> Self {
+        Self::with_entries(program, None)
+    }
+
+    /// Recover the graph of the program, which may also be entered at the
+    /// given addresses, e.g. through a computed jump.
+    pub fn with_entries(program: &[isize], entries: impl IntoIterator<Item = usize>) -> Self {
+        let mut instrs = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        leaders.extend(entries);
+
+        let mut pending: Vec<_> = leaders.iter().copied().collect();
+        while let Some(pc) = pending.pop() {
+            if pc >= program.len() || instrs.contains_key(&pc) {
+                continue;
+            }
+            let instr = match disasm::decode(program, pc) {
+                Some(Line::Instr { instr, .. }) => instr,
+                Some(Line::Data { .. }) | None => continue,
+            };
+            instrs.insert(pc, instr);
+
+            match instr {
+                Instr::Hlt => {}
+                Instr::JNZ(_, target) | Instr::JZ(_, target) => {
+                    if taken(instr) != Some(true) {
+                        leaders.insert(pc + instr.size());
+                        pending.push(pc + instr.size());
+                    }
+                    match target {
+                        Mod::Immediate(target) if target >= 0 && taken(instr) != Some(false) => {
+                            leaders.insert(target as usize);
+                            pending.push(target as usize);
+                        }
+                        _ => {}
+                    }
+                }
+                _ => pending.push(pc + instr.size()),
+            }
+        }
+
+        let mut cfg = Cfg::default();
+        for &start in &leaders {
+            let mut block = Block {
+                start,
+                instrs: Vec::new(),
+            };
+            let mut pc = start;
+            while let Some(&instr) = instrs.get(&pc) {
+                block.instrs.push((pc, instr));
+                pc += instr.size();
+
+                if let Instr::JNZ(_, target) | Instr::JZ(_, target) = instr {
+                    match (taken(instr), target) {
+                        (Some(false), _) => {}
+                        (_, Mod::Immediate(to)) if to >= 0 => cfg.edges.push(Edge::Jump {
+                            from: start,
+                            to: to as usize,
+                        }),
+                        // A negative target stops the machine with an error.
+                        (_, Mod::Immediate(_)) => {}
+                        _ => cfg.edges.push(Edge::Unresolved { from: start }),
+                    }
+                    if taken(instr) != Some(true) {
+                        cfg.edges.push(Edge::FallThrough {
+                            from: start,
+                            to: pc,
+                        });
+                    }
+                    break;
+                }
+                if instr == Instr::Hlt {
+                    break;
+                }
+                if leaders.contains(&pc) || !instrs.contains_key(&pc) {
+                    cfg.edges.push(Edge::FallThrough {
+                        from: start,
+                        to: pc,
+                    });
+                    break;
+                }
+            }
+
+            // A leader which is not code is only the target of an edge.
+            if !block.instrs.is_empty() {
+                cfg.blocks.insert(start, block);
+            }
+        }
+
+        cfg
+    }
+
+    /// Write the graph in the DOT language of Graphviz.
+    ///
+    /// Every block is a box listing its instructions. An edge to an address
+    /// which is not code leads to a dashed box, and an unresolved jump leads
+    /// to a `?` of its own.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph cfg {\n");
+        out.push_str("    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instr) in &block.instrs {
+                write!(label, "{:04}: {}\\l", address, instr).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+        }
+
+        let mut missing = BTreeSet::new();
+        for edge in &self.edges {
+            match *edge {
+                Edge::Jump { from, to } => writeln!(out, "    b{} -> b{};", from, to).unwrap(),
+                Edge::FallThrough { from, to } => {
+                    writeln!(out, "    b{} -> b{} [style=dotted];", from, to).unwrap()
+                }
+                Edge::Unresolved { from } => {
+                    writeln!(out, "    u{} [label=\"?\", shape=circle];", from).unwrap();
+                    writeln!(out, "    b{} -> u{} [style=dashed];", from, from).unwrap();
+                }
+            }
+            if let Some(to) = edge.to() {
+                if !self.blocks.contains_key(&to) {
+                    missing.insert(to);
+                }
+            }
+        }
+        for address in missing {
+            writeln!(
+                out,
+                "    b{} [label=\"{:04}: not code\", style=dashed];",
+                address, address
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+
+        out
+    }
+}
+
+#[test]
+fn test_blocks() {
+    let program = super::asm::assemble(
+        "
+        ; Count down from the input, jumping through a computed address on 0.
+        in   [n]
+loop:   out  [n]
+        add  [n], #-1, [n]
+        jnz  [n], #loop
+        jnz  #1, #done
+        .data 12345
+done:   jz   #0, [n]
+        hlt
+n:      .data 0
+",
+    )
+    .unwrap();
+    let cfg = Cfg::new(&program);
+
+    assert_eq!(
+        cfg.blocks
+            .values()
+            .map(|block| (block.start, block.end()))
+            .collect::<Vec<_>>(),
+        vec![(0, 2), (2, 11), (11, 14), (15, 18)]
+    );
+    assert_eq!(
+        cfg.edges,
+        vec![
+            Edge::FallThrough { from: 0, to: 2 },
+            Edge::Jump { from: 2, to: 2 },
+            Edge::FallThrough { from: 2, to: 11 },
+            Edge::Jump { from: 11, to: 15 },
+            Edge::Unresolved { from: 15 },
+        ]
+    );
+}
+
+#[test]
+fn test_dot() {
+    // Fall into data, and jump past the end of the program.
+    let cfg = Cfg::new(&[1006, 7, 8, 1101, 1, 1, 7, 5]);
+    assert_eq!(
+        cfg.to_dot(),
+        "digraph cfg {
+    node [shape=box, fontname=monospace];
+    b0 [label=\"0000: JZ   [7], #8\\l\"];
+    b3 [label=\"0003: ADD  #1, #1, [7]\\l\"];
+    b0 -> b8;
+    b0 -> b3 [style=dotted];
+    b3 -> b7 [style=dotted];
+    b7 [label=\"0007: not code\", style=dashed];
+    b8 [label=\"0008: not code\", style=dashed];
+}
+"
+    );
+}
+
+#[test]
+fn test_entries() {
+    // Call the subroutine at 10, which returns to 9 through the stack.
+    let program = [109, 20, 21101, 9, 0, 0, 1105, 1, 10, 99, 2105, 1, 0];
+    let starts = |cfg: Cfg| cfg.blocks.keys().copied().collect::<Vec<_>>();
+    assert_eq!(starts(Cfg::new(&program)), vec![0, 10]);
+    assert_eq!(starts(Cfg::with_entries(&program, vec![9])), vec![0, 9, 10]);
+}
@@ -0,0 +1,366 @@
+//! # coverage - which parts of a program were used
+//!
+//! The [`Coverage`] tracer records every cell executed as part of an
+//! instruction, read from, or written to, along with which ways every jump
+//! went. Afterwards, it writes an annotated listing of the program:
+//!
+//! ```text
+//! + 0000: IN   [16]
+//! + 0002: JZ   [16], #10           ; fell through
+//! + 0005: OUT  #1
+//! + 0007: JNZ  #1, #12             ; jumped
+//! - 0010: OUT  #0
+//! + 0012: OUT  [16]
+//! + 0014: HLT
+//!   0015: .data 0
+//!   0016: .data 0                  ; read, written
+//! ```
+//!
+//! Every instruction is marked with a `+` if it was executed, or a `-` if it
+//! was not. The summary holds the same in a form meant for scripts:
+//!
+//! ```text
+//! intcode-coverage 1
+//! instructions 6 7
+//! branches 2 4
+//! executed 0-9,12-14
+//! read 16
+//! written 16
+//! ```
+//!
+//! The `instructions` and `branches` lines hold how many were covered out of
+//! how many there are, counting both ways of every jump as a branch. The
+//! other lines list the addresses of the cells used in every way, as ranges.
+use super::disasm::{self, Line};
+use super::{Instr, Trace, Tracer};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// How a single cell was used.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct CellUse {
+    /// An instruction starting at the cell was executed.
+    pub instr: bool,
+
+    /// The cell was executed as part of an instruction.
+    pub code: bool,
+
+    pub read: bool,
+    pub written: bool,
+
+    /// The jump starting at the cell was taken.
+    pub jumped: bool,
+
+    /// The jump starting at the cell was not taken.
+    pub fell_through: bool,
+}
+
+/// A tracer recording how a program used its memory; see the [module
+/// documentation](self).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Coverage {
+    /// The use of every cell which was used at all, by address.
+    cells: BTreeMap<usize, CellUse>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the cell at the address was used.
+    pub fn cell(&self, address: usize) -> CellUse {
+        self.cells.get(&address).copied().unwrap_or_default()
+    }
+
+    #[inline(always)]
+    fn cell_mut(&mut self, address: usize) -> &mut CellUse {
+        self.cells.entry(address).or_default()
+    }
+
+    /// Split the program into lines as the disassembler does, except that an
+    /// executed instruction always starts a line of its own.
+    fn lines(&self, program: &[isize]) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut address = 0;
+        while let Some(mut line) = disasm::decode(program, address) {
+            let hides_instr = (address + 1..address + line.size()).any(|a| self.cell(a).instr);
+            if hides_instr && !self.cell(address).instr {
+                line = Line::Data {
+                    address,
+                    value: program[address],
+                };
+            }
+            address += line.size();
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Write an annotated listing of the program; see the [module
+    /// documentation](self).
+    pub fn listing(&self, program: &[isize]) -> String {
+        let width = std::cmp::max(4, program.len().to_string().len());
+        let mut out = String::new();
+        for line in self.lines(program) {
+            let address = line.address();
+            let marker = match line {
+                _ if self.cell(address).instr => '+',
+                Line::Instr { .. } => '-',
+                Line::Data { .. } => ' ',
+            };
+
+            let mut notes = Vec::new();
+            let used = (address..address + line.size()).map(|a| self.cell(a));
+            let (read, written) = used.fold((false, false), |(read, written), cell| {
+                (read || cell.read, written || cell.written)
+            });
+            if self.cell(address).jumped {
+                notes.push("jumped");
+            }
+            if self.cell(address).fell_through {
+                notes.push("fell through");
+            }
+            if read {
+                notes.push("read");
+            }
+            if written {
+                notes.push("written");
+            }
+
+            let text = format!("{} {:0width$}: {}", marker, address, line, width = width);
+            if notes.is_empty() {
+                writeln!(out, "{}", text).unwrap();
+            } else {
+                writeln!(out, "{:<32} ; {}", text, notes.join(", ")).unwrap();
+            }
+        }
+
+        out
+    }
+
+    /// Write a summary of the coverage of the program; see the [module
+    /// documentation](self).
+    pub fn summary(&self, program: &[isize]) -> String {
+        let (mut instrs, mut covered) = (0, 0);
+        let (mut branches, mut taken) = (0, 0);
+        for line in self.lines(program) {
+            let cell = self.cell(line.address());
+            if let Line::Instr { instr, .. } = line {
+                instrs += 1;
+                covered += cell.instr as usize;
+                if let Instr::JNZ(..) | Instr::JZ(..) = instr {
+                    branches += 2;
+                    taken += cell.jumped as usize + cell.fell_through as usize;
+                }
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "intcode-coverage 1").unwrap();
+        writeln!(out, "instructions {} {}", covered, instrs).unwrap();
+        writeln!(out, "branches {} {}", taken, branches).unwrap();
+        writeln!(out, "executed {}", self.ranges(|cell| cell.code)).unwrap();
+        writeln!(out, "read {}", self.ranges(|cell| cell.read)).unwrap();
+        writeln!(out, "written {}", self.ranges(|cell| cell.written)).unwrap();
+
+        out
+    }
+
+    /// The addresses of the cells matching the predicate, as ranges such as
+    /// `0-8,11`.
+    fn ranges(&self, used: impl Fn(&CellUse) -> bool) -> String {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (&address, cell) in &self.cells {
+            if !used(cell) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == address => *end = address,
+                _ => ranges.push((address, address)),
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{}-{}", start, end),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl<C> Tracer<C> for Coverage {
+    fn trace(&mut self, trace: &Trace<C>) {
+        self.cell_mut(trace.pc).instr = true;
+        for address in trace.pc..trace.pc + trace.instr.size() {
+            self.cell_mut(address).code = true;
+        }
+        if let Instr::JNZ(..) | Instr::JZ(..) = trace.instr {
+            // The destination is only read if the jump is taken.
+            let cell = self.cell_mut(trace.pc);
+            if trace.operands[1].is_some() {
+                cell.jumped = true;
+            } else {
+                cell.fell_through = true;
+            }
+        }
+
+        for address in trace.read_addresses() {
+            self.cell_mut(address).read = true;
+        }
+        if let Some(write) = &trace.write {
+            self.cell_mut(write.address).written = true;
+        }
+    }
+}
+
+#[test]
+fn test_coverage() {
+    use super::{IoBus, Machine};
+
+    /// Reads the same input every time, throwing away all output.
+    struct ConstIoBus(isize);
+
+    impl IoBus for ConstIoBus {
+        fn input(&mut self) -> Option<isize> {
+            Some(self.0)
+        }
+
+        fn output(&mut self, _: isize) -> bool {
+            false
+        }
+    }
+
+    let program = super::asm::assemble(
+        "
+        ; Output whether the input is non-zero, then the input itself.
+        in   [n]
+        jz   [n], #zero
+        out  #1
+        jnz  #1, #done
+zero:   out  #0
+done:   out  [n]
+        hlt
+        .data 0
+n:      .data 0
+",
+    )
+    .unwrap();
+
+    let mut coverage = Coverage::new();
+    let mut machine = Machine::new(program.clone());
+    machine.run_traced(&mut ConstIoBus(7), &mut coverage);
+    assert!(coverage.cell(0).instr);
+    assert!(!coverage.cell(10).code);
+    assert_eq!(
+        coverage.listing(&program),
+        "\
++ 0000: IN   [16]
++ 0002: JZ   [16], #10           ; fell through
++ 0005: OUT  #1
++ 0007: JNZ  #1, #12             ; jumped
+- 0010: OUT  #0
++ 0012: OUT  [16]
++ 0014: HLT
+  0015: .data 0
+  0016: .data 0                  ; read, written
+"
+    );
+    assert_eq!(
+        coverage.summary(&program),
+        "\
+intcode-coverage 1
+instructions 6 7
+branches 2 4
+executed 0-9,12-14
+read 16
+written 16
+"
+    );
+
+    let mut machine = Machine::new(program.clone());
+    machine.run_traced(&mut ConstIoBus(0), &mut coverage);
+    assert_eq!(
+        coverage.summary(&program),
+        "\
+intcode-coverage 1
+instructions 7 7
+branches 3 4
+executed 0-14
+read 16
+written 16
+"
+    );
+}
+
+#[test]
+fn test_resumed() {
+    use super::{Machine, NoIoBusImpl};
+
+    // Moves the relative base, then reads relative to it.
+    let program = vec![109, 5, 1201, 0, 0, 7, 99, 0];
+    let mut machine = Machine::new(program);
+    machine.step(&mut NoIoBusImpl::default()).unwrap();
+
+    // The tracer only sees the instructions after the relative base moved.
+    let mut coverage = Coverage::new();
+    assert!(machine
+        .run_traced(&mut NoIoBusImpl::default(), &mut coverage)
+        .has_halted());
+    assert!(coverage.cell(5).read);
+    assert!(!coverage.cell(0).read);
+    assert!(coverage.cell(7).written);
+}
+
+#[test]
+fn test_far_address() {
+    use super::{IoBus, Machine};
+
+    struct SinkIoBus;
+
+    impl IoBus for SinkIoBus {
+        fn input(&mut self) -> Option<isize> {
+            None
+        }
+
+        fn output(&mut self, _: isize) -> bool {
+            false
+        }
+    }
+
+    // Outputs a cell far past the program.
+    let program = vec![4, 1_000_000_000_000, 99];
+    let mut coverage = Coverage::new();
+    let mut machine = Machine::new(program.clone());
+    assert!(machine
+        .run_traced(&mut SinkIoBus, &mut coverage)
+        .has_halted());
+    assert!(coverage.cell(1_000_000_000_000).read);
+    assert!(coverage
+        .summary(&program)
+        .ends_with("read 1000000000000\nwritten \n"));
+}
+
+#[test]
+fn test_overflowing_address() {
+    use super::{IntcodeError, Machine, NoIoBusImpl, StopReason};
+
+    // Outputs the cell at an offset from the relative base which overflows.
+    let mut coverage = Coverage::new();
+    let mut machine = Machine::new(vec![109, 1, 204, isize::MAX, 99]);
+    let res = machine.run_traced(&mut NoIoBusImpl::default(), &mut coverage);
+    assert_eq!(
+        res.stop,
+        StopReason::Error(IntcodeError::AddressOverflow {
+            pc: 2,
+            offset: isize::MAX,
+            param: 0
+        })
+    );
+    assert!(coverage.cell(0).instr);
+    assert!(!coverage.cell(2).instr);
+}
@@ -0,0 +1,256 @@
+//! # disasm - readable listings of intcode programs
+//!
+//! The disassembler walks a program from its first cell, decoding every cell
+//! it lands on as an instruction. Anything which cannot be decoded is written
+//! as a `.data` directive instead, after which decoding continues at the very
+//! next cell.
+//!
+//! A listing looks like this:
+//!
+//! ```text
+//! 0000: ADD  [9], [10], [3]      ; 1, 9, 10, 3
+//! 0004: MUL  [3], [11], [0]      ; 2, 3, 11, 0
+//! 0008: HLT                      ; 99
+//! 0009: .data 30
+//! ```
+//!
+//! Parameters are written as `#imm` in immediate mode, `[pos]` in position
+//! mode, and `rb+off` (or `rb-off`) in relative mode.
+use super::{Instr, Mod};
+use std::fmt;
+
+/// A single decoded item of a program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Line {
+    /// An instruction starting at `address`.
+    Instr { address: usize, instr: Instr },
+
+    /// A cell at `address` which is not an instruction.
+    Data { address: usize, value: isize },
+}
+
+impl Line {
+    #[inline(always)]
+    pub fn address(&self) -> usize {
+        match *self {
+            Self::Instr { address, .. } => address,
+            Self::Data { address, .. } => address,
+        }
+    }
+
+    /// The amount of cells this line covers.
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        match *self {
+            Self::Instr { instr, .. } => instr.size(),
+            Self::Data { .. } => 1,
+        }
+    }
+}
+
+/// Decode the item at `address`, or `None` past the end of the program.
+///
+/// An instruction is only decoded if it fits in the program entirely and
+/// encodes back to the exact same cells; this keeps e.g. stray mode digits on
+/// a `Hlt` visible as data.
+pub fn decode(program: &[isize], address: usize) -> Option<Line> {
+    let value = *program.get(address)?;
+    if let Ok(instr) = Instr::parse(program, address) {
+        let end = address + instr.size();
+        if end <= program.len() && instr.encode() == program[address..end] {
+            return Some(Line::Instr { address, instr });
+        }
+    }
+
+    Some(Line::Data { address, value })
+}
+
+/// Decode the entire program.
+pub fn disassemble(program: &[isize]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while let Some(line) = decode(program, address) {
+        address += line.size();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Write an annotated listing of the entire program.
+///
+/// Every instruction is followed by a comment holding its raw cells.
+pub fn listing(program: &[isize]) -> String {
+    let width = std::cmp::max(4, program.len().to_string().len());
+    let mut out = String::new();
+    for line in disassemble(program) {
+        let address = line.address();
+        let text = line.to_string();
+        match line {
+            Line::Instr { .. } => {
+                let raw = program[address..address + line.size()]
+                    .iter()
+                    .map(isize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                out.push_str(&format!(
+                    "{:0width$}: {:<24} ; {}\n",
+                    address,
+                    text,
+                    raw,
+                    width = width
+                ));
+            }
+            Line::Data { .. } => {
+                out.push_str(&format!("{:0width$}: {}\n", address, text, width = width));
+            }
+        }
+    }
+
+    out
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Instr { instr, .. } => instr.fmt(f),
+            Self::Data { value, .. } => write!(f, ".data {}", value),
+        }
+    }
+}
+
+impl Instr {
+    /// The mnemonic of the instruction, as used in listings.
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Add(..) => "ADD",
+            Self::Mul(..) => "MUL",
+            Self::Input(..) => "IN",
+            Self::Output(..) => "OUT",
+            Self::JNZ(..) => "JNZ",
+            Self::JZ(..) => "JZ",
+            Self::LT(..) => "LT",
+            Self::EQ(..) => "EQ",
+            Self::ModRelBas(..) => "ARB",
+            Self::Hlt => "HLT",
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+        match *self {
+            Self::Add(a, b, c) | Self::Mul(a, b, c) | Self::LT(a, b, c) | Self::EQ(a, b, c) => {
+                write!(f, "{:<4} {}, {}, {}", mnemonic, a, b, c)
+            }
+            Self::JNZ(a, b) | Self::JZ(a, b) => write!(f, "{:<4} {}, {}", mnemonic, a, b),
+            Self::Input(a) | Self::Output(a) | Self::ModRelBas(a) => {
+                write!(f, "{:<4} {}", mnemonic, a)
+            }
+            Self::Hlt => f.write_str(mnemonic),
+        }
+    }
+}
+
+impl fmt::Display for Mod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Immediate(i) => write!(f, "#{}", i),
+            Self::Position(idx) => write!(f, "[{}]", idx),
+            Self::Relative(off) if off < 0 => write!(f, "rb-{}", off.unsigned_abs()),
+            Self::Relative(off) => write!(f, "rb+{}", off),
+        }
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+    assert_eq!(
+        disassemble(&program),
+        vec![
+            Line::Instr {
+                address: 0,
+                instr: Instr::Add(Mod::Position(9), Mod::Position(10), Mod::Position(3)),
+            },
+            Line::Instr {
+                address: 4,
+                instr: Instr::Mul(Mod::Position(3), Mod::Position(11), Mod::Position(0)),
+            },
+            Line::Instr {
+                address: 8,
+                instr: Instr::Hlt,
+            },
+            Line::Data {
+                address: 9,
+                value: 30,
+            },
+            Line::Data {
+                address: 10,
+                value: 40,
+            },
+            Line::Data {
+                address: 11,
+                value: 50,
+            },
+        ]
+    );
+
+    // A truncated instruction and stray mode digits aren't instructions.
+    assert_eq!(
+        disassemble(&[1099, 1, 0]),
+        vec![
+            Line::Data {
+                address: 0,
+                value: 1099
+            },
+            Line::Data {
+                address: 1,
+                value: 1
+            },
+            Line::Data {
+                address: 2,
+                value: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_decode() {
+    let program = [1101, 2, 3, 4, 1];
+    assert_eq!(
+        decode(&program, 0),
+        Some(Line::Instr {
+            address: 0,
+            instr: Instr::Add(Mod::Immediate(2), Mod::Immediate(3), Mod::Position(4)),
+        })
+    );
+    // The `Add` at 4 would run past the end.
+    assert_eq!(
+        decode(&program, 4),
+        Some(Line::Data {
+            address: 4,
+            value: 1
+        })
+    );
+    assert_eq!(decode(&program, 5), None);
+    assert_eq!(decode(&program, usize::MAX), None);
+}
+
+#[test]
+fn test_listing() {
+    let program = [109, -1, 21101, 7, 8, 3, 1005, 3, 0, 4, 3, 99, -5];
+    assert_eq!(
+        listing(&program),
+        "\
+0000: ARB  #-1                 ; 109, -1
+0002: ADD  #7, #8, rb+3        ; 21101, 7, 8, 3
+0006: JNZ  [3], #0             ; 1005, 3, 0
+0009: OUT  [3]                 ; 4, 3
+0011: HLT                      ; 99
+0012: .data -5
+"
+    );
+}
@@ -0,0 +1,399 @@
+//! # executor - machines running as futures
+//!
+//! [`Machine::run_async`] runs a machine against an [`AsyncIoBus`], whose
+//! input and output may have to wait. A machine waiting for input is parked
+//! with its pc on the `Input`, exactly as [`StopReason::NeedsInput`] leaves
+//! it, and picks up where it left off once woken.
+//!
+//! Any number of machines can run as tasks on an [`Executor`], talking to
+//! each other through [`channel`]s:
+//!
+//! ```ignore
+//! let (to_machine, input) = channel();
+//! let (output, mut from_machine) = channel();
+//! to_machine.send(7);
+//!
+//! let mut executor = Executor::new();
+//! let mut machine = Machine::new(program);
+//! executor.spawn(async move {
+//!     machine.run_async(&mut ChannelBus { input, output }).await
+//! });
+//! executor.run();
+//! ```
+//!
+//! Everything runs on the calling thread. A machine only yields to other
+//! tasks while it waits for its bus, so a machine which never does I/O keeps
+//! the executor to itself until it stops.
+use super::memory::Memory;
+use super::{IoBus, Machine, NoTracer, RunResult, StopReason};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// This defines an I/O bus for an intcode computer whose input and output may
+/// have to wait, passing cells of type `C`.
+pub trait AsyncIoBus<C = isize> {
+    /// An input instruction has been hit.
+    ///
+    /// If no input is ready, `Poll::Pending` is returned and the task is woken
+    /// once there is.
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<C>;
+
+    /// An output instruction has been hit.
+    ///
+    /// If the output cannot be taken yet, `Poll::Pending` is returned and the
+    /// same value is offered again once the task is woken.
+    fn poll_output(&mut self, cx: &mut Context<'_>, value: &C) -> Poll<()>;
+}
+
+/// A future running a machine; see [`Machine::run_async`].
+pub struct RunAsync<'a, M: Memory, B> {
+    machine: &'a mut Machine<M>,
+    io: &'a mut B,
+
+    /// An output which the bus could not take yet.
+    pending: Option<M::Cell>,
+
+    /// The instruction and cost counters when the run started.
+    start: (u64, u64),
+}
+
+impl<M: Memory> Machine<M> {
+    /// Run the machine like [`Self::run`] as a future, which is ready once the
+    /// machine halts, runs out of budget, hits a watchpoint, or does something
+    /// invalid.
+    ///
+    /// Unlike [`Self::run`], waiting for I/O never stops the machine; the
+    /// future waits for the bus instead.
+    pub fn run_async<'a, B: AsyncIoBus<M::Cell>>(
+        &'a mut self,
+        io: &'a mut B,
+    ) -> RunAsync<'a, M, B> {
+        let start = (self.instructions, self.cost);
+        RunAsync {
+            machine: self,
+            io,
+            pending: None,
+            start,
+        }
+    }
+}
+
+/// The future is never pinned structurally; it only holds references and an
+/// optional cell.
+impl<'a, M: Memory, B> Unpin for RunAsync<'a, M, B> {}
+
+impl<'a, M: Memory, B: AsyncIoBus<M::Cell>> Future for RunAsync<'a, M, B> {
+    type Output = RunResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RunResult> {
+        let this = self.get_mut();
+        loop {
+            if let Some(value) = &this.pending {
+                match this.io.poll_output(cx, value) {
+                    Poll::Ready(()) => this.pending = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            let mut bus = PolledIoBus {
+                io: &mut *this.io,
+                cx: &mut *cx,
+                pending: &mut this.pending,
+            };
+            let stop = this
+                .machine
+                .execute(&mut bus, &mut NoTracer)
+                .unwrap_or_else(StopReason::Error);
+            match stop {
+                // The output is offered again at the top of the loop.
+                StopReason::OutputPaused => {}
+                // The bus has registered the waker.
+                StopReason::NeedsInput => return Poll::Pending,
+                _ => return Poll::Ready(this.machine.result(stop, this.start)),
+            }
+        }
+    }
+}
+
+/// Polls an [`AsyncIoBus`] on behalf of the machine, stopping the machine
+/// whenever the bus has to wait.
+struct PolledIoBus<'a, 'b, B, C> {
+    io: &'a mut B,
+    cx: &'a mut Context<'b>,
+    pending: &'a mut Option<C>,
+}
+
+impl<'a, 'b, B: AsyncIoBus<C>, C> IoBus<C> for PolledIoBus<'a, 'b, B, C> {
+    fn input(&mut self) -> Option<C> {
+        match self.io.poll_input(self.cx) {
+            Poll::Ready(value) => Some(value),
+            Poll::Pending => None,
+        }
+    }
+
+    fn output(&mut self, i: C) -> bool {
+        match self.io.poll_output(self.cx, &i) {
+            Poll::Ready(()) => false,
+            Poll::Pending => {
+                *self.pending = Some(i);
+                true
+            }
+        }
+    }
+}
+
+/// The values in flight on a channel, along with the task waiting for them.
+struct Queue<C> {
+    values: VecDeque<C>,
+    waker: Option<Waker>,
+}
+
+/// The sending half of a [`channel`], which never has to wait.
+pub struct Sender<C>(Rc<RefCell<Queue<C>>>);
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<C>(Rc<RefCell<Queue<C>>>);
+
+/// Create an unbounded channel between tasks on the same thread.
+pub fn channel<C>() -> (Sender<C>, Receiver<C>) {
+    let queue = Rc::new(RefCell::new(Queue {
+        values: VecDeque::new(),
+        waker: None,
+    }));
+    (Sender(queue.clone()), Receiver(queue))
+}
+
+impl<C> Sender<C> {
+    pub fn send(&self, value: C) {
+        let mut queue = self.0.borrow_mut();
+        queue.values.push_back(value);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<C> Clone for Sender<C> {
+    fn clone(&self) -> Self {
+        Sender(self.0.clone())
+    }
+}
+
+impl<C> Receiver<C> {
+    /// Take the next value if there is one, without waiting.
+    pub fn try_recv(&mut self) -> Option<C> {
+        self.0.borrow_mut().values.pop_front()
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<C> {
+        let mut queue = self.0.borrow_mut();
+        match queue.values.pop_front() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Wait for the next value.
+    pub async fn recv(&mut self) -> C {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+/// A bus reading its input from one channel and writing its output to
+/// another.
+pub struct ChannelBus<C = isize> {
+    pub input: Receiver<C>,
+    pub output: Sender<C>,
+}
+
+impl<C: Clone> AsyncIoBus<C> for ChannelBus<C> {
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<C> {
+        self.input.poll_recv(cx)
+    }
+
+    fn poll_output(&mut self, _: &mut Context<'_>, value: &C) -> Poll<()> {
+        self.output.send(value.clone());
+        Poll::Ready(())
+    }
+}
+
+/// The tasks which have been woken, by index.
+type Woken = Arc<Mutex<VecDeque<usize>>>;
+
+struct TaskWaker {
+    task: usize,
+    woken: Woken,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.lock().unwrap().push_back(self.task);
+    }
+}
+
+/// A task which isn't done yet, along with the waker to poll it with.
+type Task<'a> = (Pin<Box<dyn Future<Output = ()> + 'a>>, Waker);
+
+/// The output of a task spawned on an [`Executor`].
+pub struct JoinHandle<T>(Rc<RefCell<Option<T>>>);
+
+impl<T> JoinHandle<T> {
+    /// Take the output of the task, if it is done and hasn't been taken yet.
+    pub fn take(&self) -> Option<T> {
+        self.0.borrow_mut().take()
+    }
+}
+
+/// A single-threaded executor, polling its tasks whenever they are woken.
+#[derive(Default)]
+pub struct Executor<'a> {
+    tasks: Vec<Option<Task<'a>>>,
+    woken: Woken,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task, which is first polled by [`Self::run`].
+    pub fn spawn<T: 'a>(&mut self, future: impl Future<Output = T> + 'a) -> JoinHandle<T> {
+        let output = Rc::new(RefCell::new(None));
+        let slot = output.clone();
+        let task = self.tasks.len();
+        let waker = Waker::from(Arc::new(TaskWaker {
+            task,
+            woken: self.woken.clone(),
+        }));
+        self.tasks.push(Some((
+            Box::pin(async move {
+                *slot.borrow_mut() = Some(future.await);
+            }),
+            waker,
+        )));
+        self.woken.lock().unwrap().push_back(task);
+        JoinHandle(output)
+    }
+
+    /// Poll the tasks until all of them are done, or none of them can make
+    /// progress any longer.
+    ///
+    /// Returns whether all tasks are done; if not, the rest are deadlocked,
+    /// e.g. machines waiting for input nobody will send.
+    pub fn run(&mut self) -> bool {
+        loop {
+            let next = self.woken.lock().unwrap().pop_front();
+            let task = match next {
+                Some(task) => task,
+                None => break,
+            };
+            let done = match &mut self.tasks[task] {
+                Some((future, waker)) => {
+                    let mut cx = Context::from_waker(waker);
+                    future.as_mut().poll(&mut cx).is_ready()
+                }
+                // A task may be woken again after it is done.
+                None => false,
+            };
+            if done {
+                self.tasks[task] = None;
+            }
+        }
+
+        self.tasks.iter().all(Option::is_none)
+    }
+}
+
+#[test]
+fn test_ring() {
+    // Add one to every input and pass it on, until the value reaches 10.
+    let program = vec![
+        3, 20, 1001, 20, 1, 20, 4, 20, 1007, 20, 10, 21, 1005, 21, 0, 99, 0, 0, 0, 0, 0, 0,
+    ];
+    let (to_first, from_second) = channel();
+    let (to_second, from_first) = channel();
+    to_first.send(0);
+
+    let mut executor = Executor::new();
+    let mut handles = Vec::new();
+    for (input, output) in vec![(from_second, to_second), (from_first, to_first)] {
+        let mut machine = Machine::new(program.clone());
+        let mut bus = ChannelBus { input, output };
+        handles.push(executor.spawn(async move {
+            let res = machine.run_async(&mut bus).await;
+            (res, bus)
+        }));
+    }
+    assert!(executor.run());
+
+    let (first, _) = handles[0].take().unwrap();
+    let (second, mut bus) = handles[1].take().unwrap();
+    assert!(first.has_halted() && second.has_halted());
+    assert_eq!((first.instructions, second.instructions), (31, 26));
+    // The last output of the first machine is left for the second one.
+    assert_eq!(bus.input.try_recv(), Some(11));
+    assert_eq!(bus.input.try_recv(), None);
+}
+
+#[test]
+fn test_deadlock() {
+    let (_, input) = channel();
+    let (output, _) = channel();
+    let mut machine = Machine::new(vec![3, 0, 99]);
+    let mut bus = ChannelBus { input, output };
+
+    let mut executor = Executor::new();
+    let handle = executor.spawn(machine.run_async(&mut bus));
+    assert!(!executor.run());
+    assert!(handle.take().is_none());
+    drop(executor);
+    assert_eq!(machine.pc(), 0);
+}
+
+#[test]
+fn test_backpressure() {
+    /// Takes a single output per wake-up.
+    struct SlowBus {
+        output: Vec<isize>,
+        ready: bool,
+    }
+
+    impl AsyncIoBus for SlowBus {
+        fn poll_input(&mut self, _: &mut Context<'_>) -> Poll<isize> {
+            Poll::Ready(5)
+        }
+
+        fn poll_output(&mut self, cx: &mut Context<'_>, value: &isize) -> Poll<()> {
+            self.ready = !self.ready;
+            if self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.output.push(*value);
+            Poll::Ready(())
+        }
+    }
+
+    let mut machine = Machine::new(vec![3, 9, 4, 9, 104, 7, 104, 8, 99, 0]);
+    let mut bus = SlowBus {
+        output: vec![],
+        ready: false,
+    };
+    let mut executor = Executor::new();
+    let handle = executor.spawn(machine.run_async(&mut bus));
+    assert!(executor.run());
+
+    let res = handle.take().unwrap();
+    assert!(res.has_halted());
+    assert_eq!(res.instructions, 5);
+    drop(executor);
+    assert_eq!(bus.output, vec![5, 7, 8]);
+}
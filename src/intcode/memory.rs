@@ -0,0 +1,211 @@
+//! # memory - where a machine keeps its cells
+//!
+//! Intcode programs may write to any non-negative address, and every cell
+//! which has never been written to holds `0`. A [`Memory`] backend decides
+//! how those cells are stored:
+//!
+//! * A `Vec<isize>` holds every cell up to the highest one written to. This
+//!   is the fastest backend, and the right one for nearly every program.
+//! * A [`PagedMemory`] holds only the pages of cells which have been written
+//!   to, so a program writing to address `10^12` needs a single page.
+//!
+//! Every write is given the limit of cells the backend may hold, so a machine
+//! can refuse to grow its memory without limit.
+use super::cell::Cell;
+use std::collections::BTreeMap;
+
+/// The amount of cells a machine may hold unless told otherwise.
+///
+/// This is far more than any puzzle program needs, while keeping a runaway
+/// program from taking all memory of the host.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+/// This defines a memory backend for an intcode computer.
+pub trait Memory {
+    /// The type of the cells the memory holds.
+    type Cell: Cell;
+
+    /// Read the cell at `address`. Cells which aren't held are `0`.
+    fn peek(&self, address: usize) -> Self::Cell;
+
+    /// Write the cell at `address`.
+    ///
+    /// If the write would make the memory hold more than `limit` cells,
+    /// nothing is written and `false` is returned.
+    fn poke(&mut self, address: usize, value: Self::Cell, limit: usize) -> bool;
+
+    /// The amount of cells the memory holds.
+    fn allocated(&self) -> usize;
+
+    /// Every run of consecutive cells the memory holds, along with the address
+    /// of its first cell, in ascending order of address.
+    fn segments(&self) -> Vec<(usize, &[Self::Cell])>;
+}
+
+impl<C: Cell> Memory for [C] {
+    type Cell = C;
+
+    #[inline(always)]
+    fn peek(&self, address: usize) -> C {
+        self.get(address).cloned().unwrap_or_default()
+    }
+
+    /// A slice cannot grow, so writing past its end always fails.
+    #[inline(always)]
+    fn poke(&mut self, address: usize, value: C, _: usize) -> bool {
+        match self.get_mut(address) {
+            Some(cell) => {
+                *cell = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn allocated(&self) -> usize {
+        self.len()
+    }
+
+    fn segments(&self) -> Vec<(usize, &[C])> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        vec![(0, self)]
+    }
+}
+
+impl<C: Cell> Memory for Vec<C> {
+    type Cell = C;
+
+    #[inline(always)]
+    fn peek(&self, address: usize) -> C {
+        self.as_slice().peek(address)
+    }
+
+    #[inline(always)]
+    fn poke(&mut self, address: usize, value: C, limit: usize) -> bool {
+        if address >= self.len() {
+            if address >= limit {
+                return false;
+            }
+
+            self.resize(address + 1, C::default());
+        }
+
+        self[address] = value;
+        true
+    }
+
+    fn allocated(&self) -> usize {
+        self.len()
+    }
+
+    fn segments(&self) -> Vec<(usize, &[C])> {
+        self.as_slice().segments()
+    }
+}
+
+/// The amount of cells in a page of a [`PagedMemory`].
+pub const PAGE_SIZE: usize = 1024;
+
+/// A memory holding only the pages of cells which have been written to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PagedMemory<C = isize> {
+    pages: BTreeMap<usize, Box<[C]>>,
+}
+
+impl<C: Cell> PagedMemory<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C> Default for PagedMemory<C> {
+    fn default() -> Self {
+        PagedMemory {
+            pages: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C: Cell> From<Vec<C>> for PagedMemory<C> {
+    fn from(program: Vec<C>) -> Self {
+        let mut memory = PagedMemory::new();
+        for (address, value) in program.into_iter().enumerate() {
+            memory.poke(address, value, usize::MAX);
+        }
+        memory
+    }
+}
+
+impl<C: Cell> Memory for PagedMemory<C> {
+    type Cell = C;
+
+    #[inline(always)]
+    fn peek(&self, address: usize) -> C {
+        match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => page[address % PAGE_SIZE].clone(),
+            None => C::default(),
+        }
+    }
+
+    #[inline(always)]
+    fn poke(&mut self, address: usize, value: C, limit: usize) -> bool {
+        let key = address / PAGE_SIZE;
+        if !self.pages.contains_key(&key) {
+            if self.allocated() + PAGE_SIZE > limit {
+                return false;
+            }
+
+            self.pages
+                .insert(key, vec![C::default(); PAGE_SIZE].into_boxed_slice());
+        }
+
+        self.pages.get_mut(&key).unwrap()[address % PAGE_SIZE] = value;
+        true
+    }
+
+    fn allocated(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    fn segments(&self) -> Vec<(usize, &[C])> {
+        self.pages
+            .iter()
+            .map(|(&key, page)| (key * PAGE_SIZE, &page[..]))
+            .collect()
+    }
+}
+
+#[test]
+fn test_vec() {
+    let mut memory: Vec<isize> = vec![1, 2, 3];
+    assert_eq!(memory.peek(1), 2);
+    assert_eq!(memory.peek(100), 0);
+
+    assert!(memory.poke(5, 7, 6));
+    assert_eq!(memory, vec![1, 2, 3, 0, 0, 7]);
+    assert!(!memory.poke(6, 7, 6));
+    assert_eq!(memory.allocated(), 6);
+}
+
+#[test]
+fn test_paged() {
+    let mut memory: PagedMemory = PagedMemory::from(vec![1, 2, 3]);
+    assert_eq!(memory.peek(2), 3);
+    assert_eq!(memory.peek(3), 0);
+
+    let far = 1_000_000_000_000;
+    assert!(memory.poke(far, 9, 2 * PAGE_SIZE));
+    assert_eq!(memory.peek(far), 9);
+    assert_eq!(memory.peek(far + 1), 0);
+    assert_eq!(memory.allocated(), 2 * PAGE_SIZE);
+
+    assert!(!memory.poke(PAGE_SIZE, 1, 2 * PAGE_SIZE));
+    assert!(memory.poke(far + 1, 1, 2 * PAGE_SIZE));
+    assert_eq!(
+        memory.segments().iter().map(|s| s.0).collect::<Vec<_>>(),
+        vec![0, far / PAGE_SIZE * PAGE_SIZE]
+    );
+}
@@ -0,0 +1,378 @@
+//! # network - machines sending packets to each other
+//!
+//! A [`Network`] boots copies of a program with the addresses `0..size`, each
+//! reading its own address as its first input. A machine sends a packet by
+//! outputting its destination address followed by the `x` and `y` values of
+//! the packet, which are then queued as the input of the destination.
+//! Reading from an empty queue gives `-1`.
+//!
+//! The machines take turns in order of their address: each runs until it
+//! finds its queue empty twice, or halts. Once a round of turns goes by
+//! without any packets being sent or received, the network is idle, and the
+//! NAT at address [`NAT`] sends the last packet it received on to machine
+//! `0`.
+//!
+//! A machine which never reads its input keeps its turn until it halts; give
+//! the machines a budget to make them share.
+use super::cell::Cell;
+use super::memory::Memory;
+use super::{IntcodeError, IoBus, Machine, StopReason};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The address of the NAT.
+pub const NAT: usize = 255;
+
+/// A packet sent from one machine to another.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct Packet<C = isize> {
+    pub dest: usize,
+    pub x: C,
+    pub y: C,
+}
+
+/// Something which happened during a round of turns.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum Event<C = isize> {
+    /// The machine at `from` sent the packet, possibly to the NAT.
+    Sent { from: usize, packet: Packet<C> },
+
+    /// The network was idle, so the NAT sent its last packet to machine `0`.
+    Woken(Packet<C>),
+}
+
+/// Why a network cannot go on.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum NetworkError {
+    /// The machine at `address` stopped with an error.
+    Machine { address: usize, error: IntcodeError },
+
+    /// The machine at `from` sent a packet to an address nobody has.
+    Unroutable { from: usize, dest: isize },
+
+    /// The network is idle, and either the NAT has nothing to send or machine
+    /// `0` has halted, so nothing will ever happen again.
+    Stalled,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Machine { address, error } => {
+                write!(f, "machine {} stopped: {}", address, error)
+            }
+            Self::Unroutable { from, dest } => {
+                write!(
+                    f,
+                    "machine {} sent a packet to unknown address {}",
+                    from, dest
+                )
+            }
+            Self::Stalled => f.write_str("the network has stalled"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Machine { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The I/O bus of a single machine in the network.
+#[derive(Debug)]
+struct Node<C> {
+    queue: VecDeque<C>,
+
+    /// The output of the machine which has not been sent as a packet yet.
+    output: Vec<C>,
+
+    /// Whether the machine found its queue empty during this turn.
+    polled: bool,
+
+    /// Whether the machine read anything from its queue during this turn.
+    received: bool,
+
+    /// How the machine stopped at the end of its last turn.
+    stop: Option<StopReason>,
+}
+
+impl<C: Cell> IoBus<C> for Node<C> {
+    fn input(&mut self) -> Option<C> {
+        match self.queue.pop_front() {
+            Some(value) => {
+                self.received = true;
+                Some(value)
+            }
+            None if self.polled => None,
+            None => {
+                self.polled = true;
+                Some(C::from_isize(-1))
+            }
+        }
+    }
+
+    fn output(&mut self, i: C) -> bool {
+        self.output.push(i);
+        false
+    }
+}
+
+/// A network of machines; see the [module documentation](self).
+#[derive(Debug)]
+pub struct Network<M: Memory = Vec<isize>> {
+    machines: Vec<Machine<M>>,
+    nodes: Vec<Node<M::Cell>>,
+
+    /// The last packet sent to the NAT.
+    nat: Option<Packet<M::Cell>>,
+
+    /// Events which happened, but have not been handed out yet by
+    /// [`Self::next_event`].
+    events: VecDeque<Event<M::Cell>>,
+}
+
+impl<M: Memory> Network<M> {
+    /// Boot `size` copies of the program in `memory`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are so many machines that one of them would have the
+    /// address of the NAT.
+    pub fn new(memory: M, size: usize) -> Self
+    where
+        M: Clone,
+    {
+        Self::with_machines((0..size).map(|_| Machine::with_memory(memory.clone())))
+    }
+
+    /// Boot the given machines, in order of their address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are so many machines that one of them would have the
+    /// address of the NAT.
+    pub fn with_machines(machines: impl IntoIterator<Item = Machine<M>>) -> Self {
+        let machines: Vec<_> = machines.into_iter().collect();
+        assert!(machines.len() <= NAT, "too many machines in the network");
+
+        let nodes = (0..machines.len())
+            .map(|address| Node {
+                queue: vec![M::Cell::from_isize(address as isize)].into(),
+                output: Vec::new(),
+                polled: false,
+                received: false,
+                stop: None,
+            })
+            .collect();
+        Network {
+            machines,
+            nodes,
+            nat: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// The amount of machines in the network.
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    /// Whether there are no machines in the network.
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// The machine at the given address.
+    pub fn machine(&self, address: usize) -> &Machine<M> {
+        &self.machines[address]
+    }
+
+    /// The values waiting to be read by the machine at the given address.
+    pub fn queue(&self, address: usize) -> &VecDeque<M::Cell> {
+        &self.nodes[address].queue
+    }
+
+    /// The last packet sent to the NAT.
+    pub fn nat(&self) -> Option<&Packet<M::Cell>> {
+        self.nat.as_ref()
+    }
+
+    /// Send a packet from outside the network, as if a machine sent it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if nobody has the destination address.
+    pub fn send(&mut self, packet: Packet<M::Cell>) {
+        self.deliver(packet);
+    }
+
+    fn deliver(&mut self, packet: Packet<M::Cell>) {
+        if packet.dest == NAT {
+            self.nat = Some(packet);
+        } else {
+            let queue = &mut self.nodes[packet.dest].queue;
+            queue.push_back(packet.x);
+            queue.push_back(packet.y);
+        }
+    }
+
+    /// Give every machine a turn, and wake the network with the NAT if it is
+    /// idle afterwards.
+    ///
+    /// Returns what happened during the round, in order.
+    pub fn round(&mut self) -> Result<Vec<Event<M::Cell>>, NetworkError> {
+        let mut events = Vec::new();
+        let mut busy = false;
+
+        for address in 0..self.machines.len() {
+            let node = &mut self.nodes[address];
+            if node.stop == Some(StopReason::Halted) {
+                continue;
+            }
+
+            node.polled = false;
+            node.received = false;
+            let res = self.machines[address].run(node);
+            if let StopReason::Error(error) = res.stop {
+                return Err(NetworkError::Machine { address, error });
+            }
+            node.stop = Some(res.stop);
+            busy |= node.received || res.stop == StopReason::BudgetExhausted;
+
+            let pending = node.output.split_off(node.output.len() / 3 * 3);
+            let mut sent = std::mem::replace(&mut node.output, pending).into_iter();
+            while let (Some(dest), Some(x), Some(y)) = (sent.next(), sent.next(), sent.next()) {
+                let dest = match dest.to_isize() {
+                    Some(dest) if dest as usize == NAT || (dest as usize) < self.machines.len() => {
+                        dest as usize
+                    }
+                    _ => {
+                        return Err(NetworkError::Unroutable {
+                            from: address,
+                            dest: dest.to_isize_saturating(),
+                        })
+                    }
+                };
+                let packet = Packet { dest, x, y };
+                events.push(Event::Sent {
+                    from: address,
+                    packet: packet.clone(),
+                });
+                self.deliver(packet);
+                busy = true;
+            }
+        }
+
+        if !busy {
+            match (self.nodes.first(), &self.nat) {
+                (Some(node), Some(packet)) if node.stop != Some(StopReason::Halted) => {
+                    let packet = Packet {
+                        dest: 0,
+                        ..packet.clone()
+                    };
+                    events.push(Event::Woken(packet.clone()));
+                    self.deliver(packet);
+                }
+                _ => return Err(NetworkError::Stalled),
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Run the network until something happens.
+    pub fn next_event(&mut self) -> Result<Event<M::Cell>, NetworkError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let events = self.round()?;
+            self.events.extend(events);
+        }
+    }
+}
+
+#[cfg(test)]
+const RELAY: &str = "
+        ; Pass every packet on to the next of three machines, adding one to
+        ; its y value. Machine 0 starts off, the last one sends to the NAT.
+        in   [addr]
+        add  [addr], #1, [next]
+        eq   [next], #3, [last]
+        jz   [last], #start
+        add  #255, #0, [next]
+start:  jnz  [addr], #poll
+        out  #1
+        out  #100
+        out  #0
+poll:   in   [x]
+        eq   [x], #-1, [empty]
+        jnz  [empty], #poll
+        in   [y]
+        add  [y], #1, [y]
+        out  [next]
+        out  [x]
+        out  [y]
+        jz   #0, #poll
+addr:   .data 0
+next:   .data 0
+last:   .data 0
+x:      .data 0
+y:      .data 0
+empty:  .data 0
+";
+
+#[test]
+fn test_relay() {
+    let program = super::asm::assemble(RELAY).unwrap();
+    let mut network = Network::new(program, 3);
+
+    let sent = |from, dest, y| Event::Sent {
+        from,
+        packet: Packet { dest, x: 100, y },
+    };
+    assert_eq!(network.next_event(), Ok(sent(0, 1, 0)));
+    assert_eq!(network.next_event(), Ok(sent(1, 2, 1)));
+    assert_eq!(network.next_event(), Ok(sent(2, NAT, 2)));
+    assert_eq!(
+        network.nat(),
+        Some(&Packet {
+            dest: NAT,
+            x: 100,
+            y: 2
+        })
+    );
+    assert_eq!(
+        network.next_event(),
+        Ok(Event::Woken(Packet {
+            dest: 0,
+            x: 100,
+            y: 2
+        }))
+    );
+    assert_eq!(network.queue(0), &[100, 2]);
+    assert_eq!(network.next_event(), Ok(sent(0, 1, 3)));
+    assert_eq!(network.next_event(), Ok(sent(1, 2, 4)));
+    assert_eq!(network.next_event(), Ok(sent(2, NAT, 5)));
+}
+
+#[test]
+fn test_errors() {
+    // Nothing is ever sent to the NAT.
+    let mut network: Network = Network::new(vec![3, 0, 3, 0, 1105, 1, 2], 2);
+    assert_eq!(network.next_event(), Err(NetworkError::Stalled));
+
+    let mut network: Network = Network::new(vec![3, 0, 104, 3, 104, 0, 104, 0, 99], 2);
+    assert_eq!(
+        network.next_event(),
+        Err(NetworkError::Unroutable { from: 0, dest: 3 })
+    );
+
+    let mut network: Network = Network::new(vec![3, 0, 99], 2);
+    assert_eq!(network.next_event(), Err(NetworkError::Stalled));
+    assert_eq!(network.machine(1).last_stop(), Some(StopReason::Halted));
+}
@@ -0,0 +1,405 @@
+//! # profile - where a program spends its time
+//!
+//! The [`Profiler`] is a tracer counting how often every instruction is
+//! executed, by address, by kind and by basic block, and timing the stretches
+//! between I/O instructions. Printing it gives a report like this:
+//!
+//! ```text
+//! 33 instructions, 4 I/O events, highest address 31
+//!
+//! hot blocks     entries  instructions   share  loop
+//! 0008                 6            18   54.5%  *
+//! 0019                 3             9   27.3%
+//! 0004                 3             3    9.1%  *
+//! ...
+//! ```
+//!
+//! Blocks are the basic blocks of the [control-flow graph](super::cfg) of the
+//! program as it is before it runs, so every instruction counts towards the
+//! same block however the program got there. Code the graph doesn't know of,
+//! such as code only reached through computed jumps, starts a block wherever
+//! the program jumps into it instead. A block entered by a jump backwards is
+//! marked as a loop.
+use super::cell::Cell;
+use super::cfg::Cfg;
+use super::{Instr, Trace, Tracer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The amount of rows in every table of the report.
+const ROWS: usize = 10;
+
+/// How a single block was executed.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct BlockStats {
+    /// How often the block was entered.
+    pub entries: u64,
+
+    /// How many instructions were executed in the block.
+    pub instructions: u64,
+
+    /// How often the block was entered by a jump backwards.
+    pub loops: u64,
+}
+
+/// The stretch of a run leading up to an I/O instruction.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct IoGap {
+    /// The address of the I/O instruction ending the stretch.
+    pub pc: usize,
+
+    /// The amount of instructions executed in the stretch, including the I/O
+    /// instruction.
+    pub instructions: u64,
+
+    pub time: Duration,
+}
+
+/// A tracer profiling the run of a machine; see the [module
+/// documentation](self).
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    /// The start of every block of the control-flow graph.
+    leaders: BTreeSet<usize>,
+
+    instructions: u64,
+    hits: BTreeMap<usize, u64>,
+    kinds: BTreeMap<&'static str, u64>,
+    blocks: BTreeMap<usize, BlockStats>,
+    io: Vec<IoGap>,
+    max_address: Option<usize>,
+
+    /// The block being executed, and the address it continues at unless the
+    /// program jumps.
+    block: usize,
+    next: Option<usize>,
+    last_pc: Option<usize>,
+
+    /// When the last I/O instruction was executed, or the first instruction
+    /// if there was none, and how many instructions had run by then.
+    last_io: Option<(Instant, u64)>,
+}
+
+impl Profiler {
+    /// Create a profiler for the program the machine is about to run.
+    ///
+    /// Cells which don't fit an `isize` are saturated to find the blocks, so
+    /// the blocks may miss them as operands, but never as op codes.
+    pub fn new<C: Cell>(program: &[C]) -> Self {
+        let program: Vec<_> = program.iter().map(Cell::to_isize_saturating).collect();
+        Profiler {
+            leaders: Cfg::new(&program).blocks.into_keys().collect(),
+            instructions: 0,
+            hits: BTreeMap::new(),
+            kinds: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            io: Vec::new(),
+            max_address: None,
+            block: 0,
+            next: None,
+            last_pc: None,
+            last_io: None,
+        }
+    }
+
+    /// The amount of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// How often the instruction at every address was executed.
+    pub fn hits(&self) -> &BTreeMap<usize, u64> {
+        &self.hits
+    }
+
+    /// How often every kind of instruction was executed, by mnemonic.
+    pub fn kinds(&self) -> &BTreeMap<&'static str, u64> {
+        &self.kinds
+    }
+
+    /// How every block was executed, by the address it starts at.
+    pub fn blocks(&self) -> &BTreeMap<usize, BlockStats> {
+        &self.blocks
+    }
+
+    /// The blocks by the amount of instructions executed in them, most first.
+    pub fn hot_blocks(&self) -> Vec<(usize, BlockStats)> {
+        let mut blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|(&pc, &stats)| (pc, stats))
+            .collect();
+        blocks.sort_by_key(|&(pc, stats)| (std::cmp::Reverse(stats.instructions), pc));
+        blocks
+    }
+
+    /// The stretches between I/O instructions, in order.
+    pub fn io_gaps(&self) -> &[IoGap] {
+        &self.io
+    }
+
+    /// The highest address executed, read or written.
+    pub fn max_address(&self) -> Option<usize> {
+        self.max_address
+    }
+
+    #[inline(always)]
+    fn touch(&mut self, address: usize) {
+        self.max_address = Some(self.max_address.map_or(address, |max| max.max(address)));
+    }
+}
+
+impl<C> Tracer<C> for Profiler {
+    fn trace(&mut self, trace: &Trace<C>) {
+        let pc = trace.pc;
+        if self.last_io.is_none() {
+            self.last_io = Some((Instant::now(), 0));
+        }
+        self.instructions += 1;
+        *self.hits.entry(pc).or_default() += 1;
+        *self.kinds.entry(trace.instr.mnemonic()).or_default() += 1;
+
+        if self.next != Some(pc) || self.leaders.contains(&pc) {
+            let block = self.blocks.entry(pc).or_default();
+            block.entries += 1;
+            if matches!(self.last_pc, Some(from) if from >= pc) {
+                block.loops += 1;
+            }
+            self.block = pc;
+        }
+        if let Some(block) = self.blocks.get_mut(&self.block) {
+            block.instructions += 1;
+        }
+        self.next = match trace.instr {
+            Instr::JNZ(..) | Instr::JZ(..) => None,
+            instr => Some(pc + instr.size()),
+        };
+        self.last_pc = Some(pc);
+
+        self.touch(pc + trace.instr.size() - 1);
+        for address in trace.read_addresses() {
+            self.touch(address);
+        }
+        if let Some(write) = &trace.write {
+            self.touch(write.address);
+        }
+
+        if let Instr::Input(_) | Instr::Output(_) = trace.instr {
+            let now = Instant::now();
+            if let Some((last, count)) = self.last_io {
+                self.io.push(IoGap {
+                    pc,
+                    instructions: self.instructions - count,
+                    time: now - last,
+                });
+            }
+            self.last_io = Some((now, self.instructions));
+        }
+    }
+}
+
+impl fmt::Display for Profiler {
+    /// Write the report as tables, each holding the top rows only.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.instructions.max(1) as f64;
+        let share = |count: u64| 100.0 * count as f64 / total;
+
+        write!(
+            f,
+            "{} instructions, {} I/O events",
+            self.instructions,
+            self.io.len()
+        )?;
+        if let Some(max) = self.max_address {
+            write!(f, ", highest address {}", max)?;
+        }
+        writeln!(f)?;
+
+        writeln!(f)?;
+        writeln!(f, "hot blocks     entries  instructions   share  loop")?;
+        for (pc, stats) in self.hot_blocks().into_iter().take(ROWS) {
+            write!(
+                f,
+                "{:04}{:>18}  {:>12}  {:>5.1}%",
+                pc,
+                stats.entries,
+                stats.instructions,
+                share(stats.instructions)
+            )?;
+            if stats.loops > 0 {
+                f.write_str("  *")?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "instructions         count   share")?;
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort_by_key(|&(kind, &count)| (std::cmp::Reverse(count), *kind));
+        for (kind, &count) in kinds.into_iter().take(ROWS) {
+            writeln!(f, "{:<16} {:>9}  {:>5.1}%", kind, count, share(count))?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "hot pcs              count   share")?;
+        let mut hits: Vec<_> = self.hits.iter().collect();
+        hits.sort_by_key(|&(pc, &count)| (std::cmp::Reverse(count), *pc));
+        for (pc, &count) in hits.into_iter().take(ROWS) {
+            writeln!(
+                f,
+                "{:04}             {:>9}  {:>5.1}%",
+                pc,
+                count,
+                share(count)
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "slowest I/O   instructions        time")?;
+        let mut gaps: Vec<_> = self.io.iter().collect();
+        gaps.sort_by_key(|gap| std::cmp::Reverse(gap.time));
+        for gap in gaps.into_iter().take(ROWS) {
+            writeln!(
+                f,
+                "{:04}          {:>12}  {:>10}",
+                gap.pc,
+                gap.instructions,
+                format!("{:?}", gap.time)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_profile() {
+    use super::{IoBus, Machine};
+
+    struct CountIoBus(usize);
+
+    impl IoBus for CountIoBus {
+        fn input(&mut self) -> Option<isize> {
+            Some(3)
+        }
+
+        fn output(&mut self, _: isize) -> bool {
+            self.0 += 1;
+            false
+        }
+    }
+
+    let program = super::asm::assemble(
+        "
+        ; Output 3, 2 and 1, adding up to each of them in a loop.
+        in   [n]
+        arb  #n
+next:   add  #0, #0, [i]
+count:  add  [i], #1, [i]
+        lt   [i], rb+0, [more]
+        jnz  [more], #count
+        out  rb+0
+        add  rb+0, #-1, rb+0
+        jnz  rb+0, #next
+        hlt
+n:      .data 0
+i:      .data 0
+more:   .data 0
+",
+    )
+    .unwrap();
+    let mut profiler = Profiler::new(&program);
+    let mut machine = Machine::new(program);
+    let mut bus = CountIoBus(0);
+    assert!(machine.run_traced(&mut bus, &mut profiler).has_halted());
+    assert_eq!(bus.0, 3);
+
+    assert_eq!(profiler.instructions(), 33);
+    assert_eq!(profiler.hits()[&8], 6);
+    assert_eq!(profiler.kinds()["ADD"], 12);
+    assert_eq!(profiler.max_address(), Some(31));
+    let stats = |entries, instructions, loops| BlockStats {
+        entries,
+        instructions,
+        loops,
+    };
+    assert_eq!(
+        profiler.hot_blocks(),
+        vec![
+            (8, stats(6, 18, 3)),
+            (19, stats(3, 9, 0)),
+            (4, stats(3, 3, 2)),
+            (0, stats(1, 2, 0)),
+            (28, stats(1, 1, 0)),
+        ]
+    );
+    assert_eq!(
+        profiler
+            .io_gaps()
+            .iter()
+            .map(|gap| (gap.pc, gap.instructions))
+            .collect::<Vec<_>>(),
+        vec![(0, 1), (19, 12), (19, 10), (19, 7)]
+    );
+
+    let report = profiler.to_string();
+    assert!(report.starts_with(
+        "\
+33 instructions, 4 I/O events, highest address 31
+
+hot blocks     entries  instructions   share  loop
+0008                 6            18   54.5%  *
+0019                 3             9   27.3%
+0004                 3             3    9.1%  *
+"
+    ));
+}
+
+#[test]
+fn test_loop_entries() {
+    use super::{Machine, NoIoBusImpl};
+
+    let program = super::asm::assemble(
+        "
+        ; Enter the loop in the middle, then count to 3 from its top.
+        jnz  #1, #mid
+top:    add  [i], #1, [i]
+mid:    lt   [i], #3, [more]
+        jnz  [more], #top
+        hlt
+i:      .data 0
+more:   .data 0
+",
+    )
+    .unwrap();
+    let mut profiler = Profiler::new(&program);
+    // Wider cells split the program into the same blocks.
+    let wide: Vec<i128> = program.iter().map(|&cell| cell as i128).collect();
+    assert_eq!(Profiler::new(&wide).leaders, profiler.leaders);
+
+    let mut machine = Machine::new(program);
+    assert!(machine
+        .run_traced(&mut NoIoBusImpl::default(), &mut profiler)
+        .has_halted());
+
+    // Both entries into the loop count towards the same blocks.
+    let stats = |entries, instructions, loops| BlockStats {
+        entries,
+        instructions,
+        loops,
+    };
+    assert_eq!(
+        profiler
+            .blocks()
+            .iter()
+            .map(|(&pc, &stats)| (pc, stats))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, stats(1, 1, 0)),
+            (3, stats(3, 3, 3)),
+            (7, stats(4, 8, 0)),
+            (14, stats(1, 1, 0)),
+        ]
+    );
+}
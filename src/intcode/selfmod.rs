@@ -0,0 +1,240 @@
+//! # selfmod - finding self-modifying code
+//!
+//! Intcode programs are free to write into their own instructions, which
+//! anything caching or translating instructions has to account for. There
+//! are two ways to find the instructions doing so:
+//!
+//! * [`scan`] looks at the program before it runs, finding every reachable
+//!   instruction writing in position mode into a reachable instruction.
+//! * [`SelfModTracer`] watches the program run, finding every write into a
+//!   cell which is executed afterwards, wherever the write was aimed.
+//!
+//! The scan misses writes in relative mode, whose address is only known at
+//! runtime, while the tracer only sees the paths the program actually took.
+//! Neither sees changes made from outside the program, such as the noun and
+//! verb patched into day2's program before it runs.
+use super::cfg::Cfg;
+use super::{Instr, Mod, Trace, Tracer};
+use std::collections::{BTreeSet, HashMap};
+
+/// The part of an instruction a write lands on.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Part {
+    Opcode,
+
+    /// The parameter with the given index, starting at `0`.
+    Operand {
+        param: u32,
+    },
+}
+
+/// A write into an instruction.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Modification {
+    /// The address of the instruction doing the write.
+    pub writer: usize,
+
+    /// The address written to.
+    pub target: usize,
+
+    /// The address of the instruction written into.
+    pub instr: usize,
+
+    pub part: Part,
+}
+
+impl Modification {
+    #[inline(always)]
+    fn new(writer: usize, target: usize, instr: usize) -> Self {
+        let part = match target - instr {
+            0 => Part::Opcode,
+            offset => Part::Operand {
+                param: offset as u32 - 1,
+            },
+        };
+        Modification {
+            writer,
+            target,
+            instr,
+            part,
+        }
+    }
+}
+
+/// The destination of an instruction, if it writes to a fixed address.
+#[inline(always)]
+fn destination(instr: Instr) -> Option<usize> {
+    match instr {
+        Instr::Add(_, _, Mod::Position(address))
+        | Instr::Mul(_, _, Mod::Position(address))
+        | Instr::LT(_, _, Mod::Position(address))
+        | Instr::EQ(_, _, Mod::Position(address))
+        | Instr::Input(Mod::Position(address)) => Some(address),
+        _ => None,
+    }
+}
+
+/// Find every reachable instruction writing into a reachable instruction,
+/// without running the program.
+///
+/// Code is found as by [`Cfg::new`]. The modifications are ordered by their
+/// writer, and then by their target.
+pub fn scan(program: &[isize]) -> Vec<Modification> {
+    let cfg = Cfg::new(program);
+    let instrs: Vec<_> = cfg
+        .blocks
+        .values()
+        .flat_map(|block| block.instrs.iter().copied())
+        .collect();
+
+    let mut found = BTreeSet::new();
+    for &(writer, instr) in &instrs {
+        let target = match destination(instr) {
+            Some(target) => target,
+            None => continue,
+        };
+        for &(address, written) in &instrs {
+            if (address..address + written.size()).contains(&target) {
+                found.insert(Modification::new(writer, target, address));
+            }
+        }
+    }
+
+    found.into_iter().collect()
+}
+
+/// A tracer finding every write into a cell which is executed afterwards.
+///
+/// A write is only found once the cell it wrote to is executed as part of an
+/// instruction, and only the last write into each cell counts.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SelfModTracer {
+    /// The instruction which last wrote into each cell.
+    writers: HashMap<usize, usize>,
+
+    found: BTreeSet<Modification>,
+}
+
+impl SelfModTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The modifications found so far, ordered by their writer, and then by
+    /// their target.
+    pub fn modifications(&self) -> impl Iterator<Item = &Modification> {
+        self.found.iter()
+    }
+}
+
+impl<C> Tracer<C> for SelfModTracer {
+    fn trace(&mut self, trace: &Trace<C>) {
+        if !self.writers.is_empty() {
+            for target in trace.pc..trace.pc + trace.instr.size() {
+                if let Some(&writer) = self.writers.get(&target) {
+                    self.found
+                        .insert(Modification::new(writer, target, trace.pc));
+                }
+            }
+        }
+
+        if let Some(write) = &trace.write {
+            self.writers.insert(write.address, trace.pc);
+        }
+    }
+}
+
+#[cfg(test)]
+const PATCHING: &str = "
+        ; Patch the multiplier of the MUL, then turn the MUL into an ADD.
+        in   [mul+2]
+        add  #1101, #0, [mul]
+        arb  #100
+        in   rb+mul-97
+mul:    mul  #2, #2, [out+1]
+out:    out  #0
+        hlt
+";
+
+#[test]
+fn test_scan() {
+    let program = super::asm::assemble(PATCHING).unwrap();
+    assert_eq!(
+        scan(&program),
+        vec![
+            Modification {
+                writer: 0,
+                target: 12,
+                instr: 10,
+                part: Part::Operand { param: 1 },
+            },
+            Modification {
+                writer: 2,
+                target: 10,
+                instr: 10,
+                part: Part::Opcode,
+            },
+            Modification {
+                writer: 10,
+                target: 15,
+                instr: 14,
+                part: Part::Operand { param: 0 },
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_tracer() {
+    use super::Machine;
+
+    struct PatchIoBus(Vec<isize>);
+
+    impl super::IoBus for PatchIoBus {
+        fn input(&mut self) -> Option<isize> {
+            Some(self.0.remove(0))
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.0.push(i);
+            false
+        }
+    }
+
+    let program = super::asm::assemble(PATCHING).unwrap();
+    let mut machine = Machine::new(program);
+    let mut bus = PatchIoBus(vec![5, 15]);
+    let mut tracer = SelfModTracer::new();
+    assert!(machine.run_traced(&mut bus, &mut tracer).has_halted());
+    assert_eq!(bus.0, vec![7]);
+
+    assert_eq!(
+        tracer.modifications().copied().collect::<Vec<_>>(),
+        vec![
+            Modification {
+                writer: 0,
+                target: 12,
+                instr: 10,
+                part: Part::Operand { param: 1 },
+            },
+            Modification {
+                writer: 2,
+                target: 10,
+                instr: 10,
+                part: Part::Opcode,
+            },
+            Modification {
+                writer: 8,
+                target: 13,
+                instr: 10,
+                part: Part::Operand { param: 2 },
+            },
+            Modification {
+                writer: 10,
+                target: 15,
+                instr: 14,
+                part: Part::Operand { param: 0 },
+            },
+        ]
+    );
+}
@@ -0,0 +1,636 @@
+//! # snapshot - saving a machine and picking it up again later
+//!
+//! A snapshot holds the entire state of a [`Machine`]: its memory, registers,
+//! and why it last stopped, so e.g. a machine waiting for input is still
+//! waiting for it once loaded. Snapshots are plain text:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! pc 2
+//! relative-base 0
+//! status needs-input
+//! instructions 1
+//! cost 1
+//! memory 0 104,7,3,0,99
+//! ```
+//!
+//! The first line holds the version of the format; loading a snapshot of an
+//! unknown version fails rather than guessing. The status is one of
+//! `running`, `halted`, `needs-input`, `output-paused`, `budget-exhausted`,
+//! `watchpoint` followed by the pc, address, old and new value, or `error`
+//! followed by the kind of the error and its fields, as in
+//! `error unknown-opcode 4 42`.
+//!
+//! The `instructions` executed and their `cost` may be left out, in which case
+//! they are `0`. The `budget` left is only written if the machine has one, and
+//! the `overflow` mode only if it is `wrap` or `saturate` rather than `trap`.
+//! Likewise, the `cost-model` is only written if it is `weighted`, followed by
+//! the weights of all op codes, and `pending` only holds the status of a
+//! watchpoint hit which is yet to be reported.
+//!
+//! Every watchpoint is written as `watch` followed by its id, its kind, and
+//! the `absolute` or `relative` start and end of its range, as in
+//! `watch 0 write absolute 16 18`.
+//!
+//! The `memory-limit` comes before the memory, whose every
+//! [segment](`super::memory::Memory::segments`) is written on a line of its
+//! own, starting with the address of its first cell.
+use super::memory::Memory;
+use super::{
+    CostModel, IntcodeError, Machine, Overflow, StopReason, WatchKind, WatchRange, Watchpoint,
+};
+use std::fmt;
+use std::io::{self, BufRead as _, Write as _};
+
+const MAGIC: &str = "intcode-snapshot";
+
+/// The version of the snapshots written by [`Machine::save`].
+pub const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+
+    /// The input doesn't start like a snapshot at all.
+    NotASnapshot,
+
+    /// The snapshot is of a version this crate cannot read.
+    UnsupportedVersion(u32),
+
+    /// The 1-indexed line cannot be read.
+    Malformed {
+        line: usize,
+    },
+
+    /// The snapshot lacks the field.
+    Missing(&'static str),
+
+    /// The memory on the 1-indexed line takes the memory past the limit of the
+    /// snapshot.
+    MemoryLimit {
+        line: usize,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "cannot read snapshot: {}", e),
+            Self::NotASnapshot => f.write_str("not an intcode snapshot"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            Self::Malformed { line } => write!(f, "malformed snapshot at line {}", line),
+            Self::Missing(field) => write!(f, "snapshot has no {}", field),
+            Self::MemoryLimit { line } => {
+                write!(
+                    f,
+                    "snapshot memory at line {} exceeds the memory limit",
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl<M: Memory + Default> Machine<M> {
+    /// Write a snapshot of the machine.
+    pub fn save(&self, out: impl io::Write) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        writeln!(out, "{} {}", MAGIC, VERSION)?;
+        writeln!(out, "pc {}", self.pc)?;
+        writeln!(out, "relative-base {}", self.relative_base)?;
+        writeln!(out, "status {}", Status(self.last_stop))?;
+        writeln!(out, "instructions {}", self.instructions)?;
+        writeln!(out, "cost {}", self.cost)?;
+        if let Some(budget) = self.budget {
+            writeln!(out, "budget {}", budget)?;
+        }
+        match self.overflow {
+            Overflow::Trap => {}
+            Overflow::Wrap => writeln!(out, "overflow wrap")?,
+            Overflow::Saturate => writeln!(out, "overflow saturate")?,
+        }
+        if let CostModel::Weighted(weights) = self.cost_model {
+            write!(out, "cost-model weighted ")?;
+            for (idx, weight) in weights.iter().enumerate() {
+                if idx > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "{}", weight)?;
+            }
+            writeln!(out)?;
+        }
+        if let Some(hit) = self.pending_hit {
+            writeln!(out, "pending {}", Status(Some(hit)))?;
+        }
+        for (id, watchpoint) in self.watchpoints() {
+            let kind = match watchpoint.kind {
+                WatchKind::Read => "read",
+                WatchKind::Write => "write",
+                WatchKind::Access => "access",
+            };
+            write!(out, "watch {} {} ", id.0, kind)?;
+            match &watchpoint.range {
+                WatchRange::Absolute(range) => {
+                    writeln!(out, "absolute {} {}", range.start, range.end)?
+                }
+                WatchRange::Relative(range) => {
+                    writeln!(out, "relative {} {}", range.start, range.end)?
+                }
+            }
+        }
+        writeln!(out, "memory-limit {}", self.memory_limit)?;
+        for (start, cells) in self.memory.segments() {
+            write!(out, "memory {} ", start)?;
+            for (idx, cell) in cells.iter().enumerate() {
+                if idx > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "{}", cell)?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    /// Read a machine back from a snapshot.
+    ///
+    /// The memory of the snapshot may not exceed its memory limit, or the
+    /// default one if the snapshot has none.
+    pub fn load(input: impl io::Read) -> Result<Self, SnapshotError> {
+        let mut lines = io::BufReader::new(input).lines();
+
+        let header = lines.next().ok_or(SnapshotError::NotASnapshot)??;
+        let version = match header.split_once(' ') {
+            Some((MAGIC, version)) => version
+                .parse()
+                .map_err(|_| SnapshotError::Malformed { line: 1 })?,
+            _ => return Err(SnapshotError::NotASnapshot),
+        };
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut machine = Self::default();
+        let mut pc = None;
+        let mut relative_base = None;
+        let mut status = None;
+        for (idx, line) in lines.enumerate() {
+            let line = line?;
+            let malformed = || SnapshotError::Malformed { line: idx + 2 };
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "pc" => pc = Some(value.parse().map_err(|_| malformed())?),
+                "relative-base" => relative_base = Some(value.parse().map_err(|_| malformed())?),
+                "status" => status = Some(parse_status(value).ok_or_else(malformed)?),
+                "instructions" => machine.instructions = value.parse().map_err(|_| malformed())?,
+                "cost" => machine.cost = value.parse().map_err(|_| malformed())?,
+                "budget" => machine.budget = Some(value.parse().map_err(|_| malformed())?),
+                "overflow" => {
+                    machine.overflow = match value {
+                        "wrap" => Overflow::Wrap,
+                        "saturate" => Overflow::Saturate,
+                        _ => return Err(malformed()),
+                    }
+                }
+                "cost-model" => {
+                    let weights = value.strip_prefix("weighted ").ok_or_else(malformed)?;
+                    let mut weights = weights.split(',');
+                    let mut weighted = [0; 10];
+                    for weight in &mut weighted {
+                        let next = weights.next().ok_or_else(malformed)?;
+                        *weight = next.parse().map_err(|_| malformed())?;
+                    }
+                    if weights.next().is_some() {
+                        return Err(malformed());
+                    }
+                    machine.cost_model = CostModel::Weighted(weighted);
+                }
+                "pending" => match parse_status(value) {
+                    Some(Some(hit)) => machine.pending_hit = Some(hit),
+                    _ => return Err(malformed()),
+                },
+                "watch" => {
+                    let (id, watchpoint) = parse_watchpoint(value).ok_or_else(malformed)?;
+                    if machine.watchpoints.len() <= id {
+                        machine.watchpoints.resize(id + 1, None);
+                    }
+                    if machine.watchpoints[id].replace(watchpoint).is_some() {
+                        return Err(malformed());
+                    }
+                }
+                "memory-limit" => machine.memory_limit = value.parse().map_err(|_| malformed())?,
+                "memory" => {
+                    let (start, cells) = value.split_once(' ').ok_or_else(malformed)?;
+                    let start: usize = start.parse().map_err(|_| malformed())?;
+                    if cells.is_empty() {
+                        continue;
+                    }
+
+                    for (offset, cell) in cells.split(',').enumerate() {
+                        let cell = cell.parse().map_err(|_| malformed())?;
+                        let address = start.checked_add(offset).ok_or_else(malformed)?;
+                        if !machine.memory.poke(address, cell, machine.memory_limit) {
+                            return Err(SnapshotError::MemoryLimit { line: idx + 2 });
+                        }
+                    }
+                }
+                _ => return Err(malformed()),
+            }
+        }
+
+        machine.pc = pc.ok_or(SnapshotError::Missing("pc"))?;
+        machine.relative_base = relative_base.ok_or(SnapshotError::Missing("relative base"))?;
+        machine.last_stop = status.ok_or(SnapshotError::Missing("status"))?;
+        Ok(machine)
+    }
+}
+
+fn parse_watchpoint(watchpoint: &str) -> Option<(usize, Watchpoint)> {
+    let mut words = watchpoint.split(' ');
+    let id = words.next()?.parse().ok()?;
+    let kind = match words.next()? {
+        "read" => WatchKind::Read,
+        "write" => WatchKind::Write,
+        "access" => WatchKind::Access,
+        _ => return None,
+    };
+    let range = match words.next()? {
+        "absolute" => {
+            WatchRange::Absolute(words.next()?.parse().ok()?..words.next()?.parse().ok()?)
+        }
+        "relative" => {
+            WatchRange::Relative(words.next()?.parse().ok()?..words.next()?.parse().ok()?)
+        }
+        _ => return None,
+    };
+
+    match words.next() {
+        Some(_) => None,
+        None => Some((id, Watchpoint { kind, range })),
+    }
+}
+
+/// The last stop of a machine, as written in a snapshot.
+struct Status(Option<StopReason>);
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let e = match self.0 {
+            None => return f.write_str("running"),
+            Some(StopReason::Halted) => return f.write_str("halted"),
+            Some(StopReason::NeedsInput) => return f.write_str("needs-input"),
+            Some(StopReason::OutputPaused) => return f.write_str("output-paused"),
+            Some(StopReason::BudgetExhausted) => return f.write_str("budget-exhausted"),
+            Some(StopReason::Watchpoint {
+                pc,
+                address,
+                old,
+                new,
+            }) => return write!(f, "watchpoint {} {} {} {}", pc, address, old, new),
+            Some(StopReason::Error(e)) => e,
+        };
+
+        match e {
+            IntcodeError::UnknownOpcode { pc, value } => {
+                write!(f, "error unknown-opcode {} {}", pc, value)
+            }
+            IntcodeError::UnknownMode { pc, value, param } => {
+                write!(f, "error unknown-mode {} {} {}", pc, value, param)
+            }
+            IntcodeError::ImmediateDestination { pc, value, param } => {
+                write!(f, "error immediate-destination {} {} {}", pc, value, param)
+            }
+            IntcodeError::NegativeAddress { pc, value, param } => {
+                write!(f, "error negative-address {} {} {}", pc, value, param)
+            }
+            IntcodeError::AddressOverflow { pc, offset, param } => {
+                write!(f, "error address-overflow {} {} {}", pc, offset, param)
+            }
+            IntcodeError::MemoryLimit { pc, address, param } => {
+                write!(f, "error memory-limit {} {} {}", pc, address, param)
+            }
+            IntcodeError::Overflow { pc, lhs, rhs } => {
+                write!(f, "error overflow {} {} {}", pc, lhs, rhs)
+            }
+        }
+    }
+}
+
+fn parse_status(status: &str) -> Option<Option<StopReason>> {
+    let mut words = status.split(' ');
+    let stop = match words.next()? {
+        "running" => None,
+        "halted" => Some(StopReason::Halted),
+        "needs-input" => Some(StopReason::NeedsInput),
+        "output-paused" => Some(StopReason::OutputPaused),
+        "budget-exhausted" => Some(StopReason::BudgetExhausted),
+        "watchpoint" => Some(StopReason::Watchpoint {
+            pc: words.next()?.parse().ok()?,
+            address: words.next()?.parse().ok()?,
+            old: words.next()?.parse().ok()?,
+            new: words.next()?.parse().ok()?,
+        }),
+        "error" => {
+            let kind = words.next()?;
+            let pc = words.next()?.parse().ok()?;
+            // The value is an address for some errors, so parse it per kind.
+            let value = words.next()?;
+            let mut param = || words.next()?.parse().ok();
+            let e = match kind {
+                "unknown-opcode" => IntcodeError::UnknownOpcode {
+                    pc,
+                    value: value.parse().ok()?,
+                },
+                "unknown-mode" => IntcodeError::UnknownMode {
+                    pc,
+                    value: value.parse().ok()?,
+                    param: param()?,
+                },
+                "immediate-destination" => IntcodeError::ImmediateDestination {
+                    pc,
+                    value: value.parse().ok()?,
+                    param: param()?,
+                },
+                "negative-address" => IntcodeError::NegativeAddress {
+                    pc,
+                    value: value.parse().ok()?,
+                    param: param()?,
+                },
+                "address-overflow" => IntcodeError::AddressOverflow {
+                    pc,
+                    offset: value.parse().ok()?,
+                    param: param()?,
+                },
+                "memory-limit" => IntcodeError::MemoryLimit {
+                    pc,
+                    address: value.parse().ok()?,
+                    param: param()?,
+                },
+                "overflow" => IntcodeError::Overflow {
+                    pc,
+                    lhs: value.parse().ok()?,
+                    rhs: words.next()?.parse().ok()?,
+                },
+                _ => return None,
+            };
+            Some(StopReason::Error(e))
+        }
+        _ => return None,
+    };
+
+    match words.next() {
+        Some(_) => None,
+        None => Some(stop),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    struct OneShotIoBus(Option<isize>, Vec<isize>);
+
+    impl super::IoBus for OneShotIoBus {
+        fn input(&mut self) -> Option<isize> {
+            self.0.take()
+        }
+
+        fn output(&mut self, i: isize) -> bool {
+            self.1.push(i);
+            false
+        }
+    }
+
+    // Outputs 7, then reads a value and outputs it twice.
+    let program = vec![104, 7, 3, 0, 4, 0, 4, 0, 99];
+    let mut machine = Machine::new(program);
+    let mut bus = OneShotIoBus(None, vec![]);
+    assert_eq!(machine.run(&mut bus).stop, StopReason::NeedsInput);
+
+    let mut saved = Vec::new();
+    machine.save(&mut saved).unwrap();
+    assert_eq!(
+        String::from_utf8(saved.clone()).unwrap(),
+        "\
+intcode-snapshot 1
+pc 2
+relative-base 0
+status needs-input
+instructions 1
+cost 1
+memory-limit 16777216
+memory 0 104,7,3,0,4,0,4,0,99
+"
+    );
+
+    let mut loaded = Machine::load(saved.as_slice()).unwrap();
+    assert_eq!(loaded, machine);
+
+    bus.0 = Some(-3);
+    assert!(loaded.run(&mut bus).has_halted());
+    assert_eq!(bus.1, vec![7, -3, -3]);
+
+    let mut saved = Vec::new();
+    loaded.set_budget(Some(10));
+    loaded.save(&mut saved).unwrap();
+    assert_eq!(Machine::load(saved.as_slice()).unwrap(), loaded);
+}
+
+#[test]
+fn test_statuses() {
+    let stops = [
+        None,
+        Some(StopReason::Halted),
+        Some(StopReason::OutputPaused),
+        Some(StopReason::BudgetExhausted),
+        Some(StopReason::Watchpoint {
+            pc: 6,
+            address: 1 << 40,
+            old: -1,
+            new: isize::MIN,
+        }),
+        Some(StopReason::Error(IntcodeError::UnknownOpcode {
+            pc: 4,
+            value: 42,
+        })),
+        Some(StopReason::Error(IntcodeError::NegativeAddress {
+            pc: 1,
+            value: -2,
+            param: 1,
+        })),
+        Some(StopReason::Error(IntcodeError::AddressOverflow {
+            pc: 2,
+            offset: isize::MIN,
+            param: 0,
+        })),
+        Some(StopReason::Error(IntcodeError::MemoryLimit {
+            pc: 1,
+            address: 1 << 40,
+            param: 2,
+        })),
+        Some(StopReason::Error(IntcodeError::Overflow {
+            pc: 0,
+            lhs: -3,
+            rhs: isize::MAX,
+        })),
+    ];
+    for &stop in &stops {
+        let mut machine = Machine::new(vec![]);
+        machine.last_stop = stop;
+
+        let mut saved = Vec::new();
+        machine.save(&mut saved).unwrap();
+        assert_eq!(Machine::load(saved.as_slice()).unwrap(), machine);
+    }
+}
+
+#[test]
+fn test_errors() {
+    let load = |s: &str| {
+        Machine::<Vec<isize>>::load(s.as_bytes())
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(load(""), "not an intcode snapshot");
+    assert_eq!(load("1,2,3"), "not an intcode snapshot");
+    assert_eq!(
+        load("intcode-snapshot 2\n"),
+        "unsupported snapshot version 2"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\npc 0\nrelative-base -1\n"),
+        "malformed snapshot at line 3"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\npc 0\nrelative-base 0\nmemory 0 99\n"),
+        "snapshot has no status"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\nmemory 1000000000000 1\n"),
+        "snapshot memory at line 2 exceeds the memory limit"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\nmemory 104,7,99\n"),
+        "malformed snapshot at line 2"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\noverflow trap\n"),
+        "malformed snapshot at line 2"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\nmemory-limit 1\nmemory 0 1,2\n"),
+        "snapshot memory at line 3 exceeds the memory limit"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\npending running\n"),
+        "malformed snapshot at line 2"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\nwatch 1 read absolute 0 1\nwatch 1 write relative 0 1\n"),
+        "malformed snapshot at line 3"
+    );
+    assert_eq!(
+        load("intcode-snapshot 1\ncost-model weighted 1,2,3\n"),
+        "malformed snapshot at line 2"
+    );
+}
+
+#[test]
+fn test_overflow() {
+    // Overflows, then halts.
+    let mut machine = Machine::new(vec![1101, isize::MAX, 1, 0, 99]);
+    machine.set_overflow(Overflow::Wrap);
+
+    let mut saved = Vec::new();
+    machine.save(&mut saved).unwrap();
+    assert!(String::from_utf8(saved.clone())
+        .unwrap()
+        .contains("\noverflow wrap\n"));
+    let mut loaded = Machine::load(saved.as_slice()).unwrap();
+    assert_eq!(loaded, machine);
+    assert!(loaded.run(&mut super::NoIoBusImpl::default()).has_halted());
+}
+
+#[test]
+fn test_paged() {
+    use super::memory::PagedMemory;
+
+    let mut memory: PagedMemory = PagedMemory::new();
+    memory.poke(3, 99, usize::MAX);
+    memory.poke(1 << 40, -1, usize::MAX);
+    let machine = Machine::with_memory(memory);
+
+    let mut saved = Vec::new();
+    machine.save(&mut saved).unwrap();
+    assert_eq!(Machine::load(saved.as_slice()).unwrap(), machine);
+
+    // The memory runs past the last address.
+    let snapshot = format!("intcode-snapshot 1\nmemory {} 1,2\n", usize::MAX);
+    assert_eq!(
+        Machine::<PagedMemory>::load(snapshot.as_bytes())
+            .unwrap_err()
+            .to_string(),
+        "malformed snapshot at line 2"
+    );
+}
+
+#[test]
+fn test_settings() {
+    use super::NoIoBusImpl;
+
+    // Outputs the cell at 5, then halts.
+    let mut weights = [1; 10];
+    weights[4] = 3;
+    let mut machine = Machine::new(vec![4, 5, 99, 0, 0, 42]);
+    machine.set_cost_model(CostModel::Weighted(weights));
+    machine.set_memory_limit(100);
+    let gone = machine.watch(WatchKind::Write, 0..1);
+    machine.watch(WatchKind::Read, 5..6);
+    machine.watch_relative(WatchKind::Access, -2..3);
+    machine.unwatch(gone);
+
+    let mut saved = Vec::new();
+    machine.save(&mut saved).unwrap();
+    let loaded = Machine::load(saved.as_slice()).unwrap();
+    assert_eq!(loaded, machine);
+    assert_eq!(
+        loaded.watchpoints().map(|(id, _)| id).collect::<Vec<_>>(),
+        machine.watchpoints().map(|(id, _)| id).collect::<Vec<_>>(),
+    );
+
+    // The output pauses, which leaves the read of the watched cell pending.
+    let mut bus = NoIoBusImpl { panic: false };
+    assert_eq!(machine.run(&mut bus).stop, StopReason::OutputPaused);
+    let mut saved = Vec::new();
+    machine.save(&mut saved).unwrap();
+    let mut loaded = Machine::load(saved.as_slice()).unwrap();
+    assert_eq!(loaded, machine);
+    assert_eq!(
+        loaded.run(&mut bus).stop,
+        StopReason::Watchpoint {
+            pc: 0,
+            address: 5,
+            old: 42,
+            new: 42
+        }
+    );
+    assert_eq!(loaded.cost(), 3);
+}
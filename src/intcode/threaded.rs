@@ -0,0 +1,179 @@
+//! # threaded - machines running on their own threads
+//!
+//! An [`MpscBus`] connects a machine to `std::sync::mpsc` channels: input
+//! blocks until a value arrives, and output is sent on without waiting. With
+//! [`run_threaded`], every machine gets a thread of its own:
+//!
+//! ```ignore
+//! let (to_machine, input) = channel();
+//! let (output, from_machine) = channel();
+//! to_machine.send(7).unwrap();
+//!
+//! let bus = MpscBus { input, output };
+//! drop(to_machine);
+//! let results = run_threaded(vec![(Machine::new(program), bus)]);
+//! let answer = from_machine.recv().unwrap();
+//! ```
+//!
+//! Unlike the [`executor`](super::executor), nothing notices when machines
+//! wait on each other forever. A machine only gives up waiting for input once
+//! every sender of its channel has been dropped, stopping with
+//! [`StopReason::NeedsInput`](super::StopReason::NeedsInput). A bus drops its
+//! output sender as soon as its machine stops, so once one machine of a ring
+//! stops the others follow, provided the caller keeps no senders of its own.
+use super::memory::Memory;
+use super::{IoBus, Machine, RunResult};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+/// A bus reading its input from one channel and writing its output to
+/// another.
+pub struct MpscBus<C = isize> {
+    pub input: Receiver<C>,
+    pub output: Sender<C>,
+}
+
+impl<C> IoBus<C> for MpscBus<C> {
+    /// Wait for the next value, or stop if there won't be any.
+    fn input(&mut self) -> Option<C> {
+        self.input.recv().ok()
+    }
+
+    /// Send the value on, or stop if nobody is listening anymore.
+    fn output(&mut self, i: C) -> bool {
+        self.output.send(i).is_err()
+    }
+}
+
+/// Run every machine against its bus on a thread of its own, and wait for all
+/// of them to stop.
+///
+/// The output sender of each bus is dropped when its machine stops. The
+/// machines and input receivers are handed back along with the result of
+/// each run, in the order they were given. A panic on any of the threads is
+/// passed on.
+pub fn run_threaded<M>(
+    machines: impl IntoIterator<Item = (Machine<M>, MpscBus<M::Cell>)>,
+) -> Vec<(Machine<M>, Receiver<M::Cell>, RunResult)>
+where
+    M: Memory + Send + 'static,
+    M::Cell: Send + 'static,
+{
+    let handles: Vec<_> = machines
+        .into_iter()
+        .map(|(mut machine, mut bus)| {
+            thread::spawn(move || {
+                let res = machine.run(&mut bus);
+                let MpscBus { input, output } = bus;
+                drop(output);
+                (machine, input, res)
+            })
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .map(|handle| match handle.join() {
+            Ok(stopped) => stopped,
+            Err(panic) => std::panic::resume_unwind(panic),
+        })
+        .collect()
+}
+
+#[test]
+fn test_ring() {
+    use std::sync::mpsc::channel;
+
+    // Add one to every input and pass it on, until the value reaches 10.
+    let program = vec![
+        3, 20, 1001, 20, 1, 20, 4, 20, 1007, 20, 10, 21, 1005, 21, 0, 99, 0, 0, 0, 0, 0, 0,
+    ];
+    let (to_first, from_second) = channel();
+    let (to_second, from_first) = channel();
+    to_first.send(0).unwrap();
+
+    let results = run_threaded(vec![
+        (
+            Machine::new(program.clone()),
+            MpscBus {
+                input: from_second,
+                output: to_second,
+            },
+        ),
+        (
+            Machine::new(program),
+            MpscBus {
+                input: from_first,
+                output: to_first,
+            },
+        ),
+    ]);
+
+    let (_, _, first) = &results[0];
+    let (_, input, second) = &results[1];
+    assert!(first.has_halted() && second.has_halted());
+    assert_eq!((first.instructions, second.instructions), (31, 26));
+    // The last output of the first machine is left for the second one.
+    assert_eq!(input.try_recv(), Ok(11));
+    assert!(input.try_recv().is_err());
+}
+
+#[test]
+fn test_ring_error() {
+    use super::{IntcodeError, StopReason};
+    use std::sync::mpsc::channel;
+
+    // The first machine faults before passing anything on, which leaves the
+    // others waiting on it.
+    let relay = vec![3, 9, 4, 9, 1105, 1, 0, 99, 0, 0];
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| channel()).unzip();
+    senders[1].send(1).unwrap();
+    let machines: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(idx, input)| {
+            let program = if idx == 0 { vec![98] } else { relay.clone() };
+            let bus = MpscBus {
+                input,
+                output: senders[(idx + 1) % senders.len()].clone(),
+            };
+            (Machine::new(program), bus)
+        })
+        .collect();
+    drop(senders);
+
+    let results = run_threaded(machines);
+    assert_eq!(
+        results[0].2.stop,
+        StopReason::Error(IntcodeError::UnknownOpcode { pc: 0, value: 98 })
+    );
+    assert_eq!(results[1].2.stop, StopReason::NeedsInput);
+    assert_eq!(results[2].2.stop, StopReason::NeedsInput);
+    // The value went around to the first machine, which never took it.
+    assert_eq!(results[0].1.try_recv(), Ok(1));
+}
+
+#[test]
+fn test_disconnected() {
+    use super::StopReason;
+    use std::sync::mpsc::channel;
+
+    // Nobody is left to send input, or to take output.
+    let (_, input) = channel::<isize>();
+    let (output, _) = channel();
+    let results = run_threaded(vec![(
+        Machine::new(vec![3, 0, 99]),
+        MpscBus { input, output },
+    )]);
+    assert_eq!(results[0].2.stop, StopReason::NeedsInput);
+    assert_eq!(results[0].2.pc, 0);
+
+    let (_, input) = channel();
+    let (output, _) = channel();
+    let results = run_threaded(vec![(
+        Machine::new(vec![104, 1, 104, 2, 99]),
+        MpscBus { input, output },
+    )]);
+    assert_eq!(results[0].2.stop, StopReason::OutputPaused);
+    assert_eq!(results[0].2.pc, 2);
+}
@@ -0,0 +1,229 @@
+//! # topology - graphs of machines feeding each other
+//!
+//! A [`Topology`] declares which machines there are, what each of them is
+//! given as input up front, and whose output goes to whose input:
+//!
+//! ```ignore
+//! let mut topology = Topology::new();
+//! let first = topology.node(Machine::new(program.clone()), vec![5, 0]);
+//! let second = topology.node(Machine::new(program), vec![6]);
+//! topology.edge(first, second);
+//! topology.edge(second, first);
+//! let report = topology.run();
+//! let thrust = report[second].outputs.last();
+//! ```
+//!
+//! A machine with several outgoing edges sends every output along all of
+//! them. A machine with several incoming edges reads their values in the
+//! order they were sent. The machines run as tasks on an
+//! [`Executor`](super::executor::Executor) until all of them have halted, or
+//! none of them can go on.
+use super::executor::{channel, AsyncIoBus, Executor, Receiver, Sender};
+use super::memory::Memory;
+use super::{Machine, RunResult};
+use std::ops::Index;
+use std::task::{Context, Poll};
+
+/// A machine in a [`Topology`].
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct NodeId(usize);
+
+/// A graph of machines; see the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct Topology<M: Memory = Vec<isize>> {
+    nodes: Vec<(Machine<M>, Vec<M::Cell>)>,
+    edges: Vec<(NodeId, NodeId)>,
+}
+
+/// How a [`Topology`] ended up.
+#[derive(Debug)]
+pub struct Report<M: Memory = Vec<isize>> {
+    /// Every node, in the order they were added.
+    pub nodes: Vec<NodeReport<M>>,
+
+    /// Whether every machine ran to completion, rather than some of them
+    /// waiting for input which never came.
+    pub finished: bool,
+}
+
+/// How a single node of a [`Topology`] ended up.
+#[derive(Debug)]
+pub struct NodeReport<M: Memory = Vec<isize>> {
+    pub machine: Machine<M>,
+
+    /// The result of the whole run, unless the machine is still waiting for
+    /// input.
+    pub result: Option<RunResult>,
+
+    /// Everything the machine output, in order.
+    pub outputs: Vec<M::Cell>,
+
+    /// The input which was sent to the machine, but never read.
+    pub unread: Vec<M::Cell>,
+}
+
+impl<M: Memory> Topology<M> {
+    pub fn new() -> Self {
+        Topology {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// Add a machine, which reads the given inputs before anything sent to
+    /// it.
+    pub fn node(
+        &mut self,
+        machine: Machine<M>,
+        inputs: impl IntoIterator<Item = M::Cell>,
+    ) -> NodeId {
+        self.nodes.push((machine, inputs.into_iter().collect()));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Send the output of one machine to the input of another, or of itself.
+    pub fn edge(&mut self, from: NodeId, to: NodeId) {
+        self.edges.push((from, to));
+    }
+
+    /// Run every machine until all of them have halted, or none of them can
+    /// go on.
+    pub fn run(self) -> Report<M> {
+        let (senders, receivers): (Vec<_>, Vec<_>) = self.nodes.iter().map(|_| channel()).unzip();
+        let mut machines = Vec::new();
+        let mut buses = Vec::new();
+        let nodes = self.nodes.into_iter().zip(receivers);
+        for (idx, ((machine, inputs), input)) in nodes.enumerate() {
+            for value in inputs {
+                senders[idx].send(value);
+            }
+            let targets = self
+                .edges
+                .iter()
+                .filter(|(from, _)| from.0 == idx)
+                .map(|(_, to)| senders[to.0].clone())
+                .collect();
+            machines.push(machine);
+            buses.push(GraphBus {
+                input,
+                targets,
+                outputs: Vec::new(),
+            });
+        }
+
+        let mut executor = Executor::new();
+        let handles: Vec<_> = machines
+            .iter_mut()
+            .zip(buses.iter_mut())
+            .map(|(machine, bus)| executor.spawn(machine.run_async(bus)))
+            .collect();
+        let finished = executor.run();
+        drop(executor);
+
+        let nodes = machines
+            .into_iter()
+            .zip(buses)
+            .zip(handles)
+            .map(|((machine, mut bus), handle)| {
+                let unread = std::iter::from_fn(|| bus.input.try_recv()).collect();
+                NodeReport {
+                    machine,
+                    result: handle.take(),
+                    outputs: bus.outputs,
+                    unread,
+                }
+            })
+            .collect();
+        Report { nodes, finished }
+    }
+}
+
+impl<M: Memory> Index<NodeId> for Report<M> {
+    type Output = NodeReport<M>;
+
+    fn index(&self, id: NodeId) -> &NodeReport<M> {
+        &self.nodes[id.0]
+    }
+}
+
+/// The bus of a node, recording its output as it sends it along the edges.
+struct GraphBus<C> {
+    input: Receiver<C>,
+    targets: Vec<Sender<C>>,
+    outputs: Vec<C>,
+}
+
+impl<C: Clone> AsyncIoBus<C> for GraphBus<C> {
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<C> {
+        self.input.poll_recv(cx)
+    }
+
+    fn poll_output(&mut self, _: &mut Context<'_>, value: &C) -> Poll<()> {
+        for target in &self.targets {
+            target.send(value.clone());
+        }
+        self.outputs.push(value.clone());
+        Poll::Ready(())
+    }
+}
+
+#[test]
+fn test_fan_out_fan_in() {
+    // Double every input.
+    let double = vec![3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0];
+    let sum = super::asm::assemble(
+        "
+        ; Output the sum of four inputs.
+loop:   in   [x]
+        add  [sum], [x], [sum]
+        add  [n], #-1, [n]
+        jnz  [n], #loop
+        out  [sum]
+        hlt
+x:      .data 0
+sum:    .data 0
+n:      .data 4
+",
+    )
+    .unwrap();
+
+    let mut topology = Topology::new();
+    let source = topology.node(Machine::new(vec![104, 1, 104, 2, 99]), vec![]);
+    let left = topology.node(Machine::new(double.clone()), vec![]);
+    let right = topology.node(Machine::new(double), vec![10]);
+    let sink = topology.node(Machine::new(sum), vec![]);
+    topology.edge(source, left);
+    topology.edge(source, right);
+    topology.edge(left, sink);
+    topology.edge(right, sink);
+    let report = topology.run();
+
+    // The doublers are left waiting for more input.
+    assert!(!report.finished);
+    assert!(report[source].result.unwrap().has_halted());
+    assert!(report[left].result.is_none());
+    assert_eq!(report[left].outputs, vec![2, 4]);
+    assert_eq!(report[right].outputs, vec![20, 2, 4]);
+    assert!(report[sink].result.unwrap().has_halted());
+    assert_eq!(report[sink].outputs, vec![28]);
+    assert_eq!(report[sink].unread, vec![4]);
+}
+
+#[test]
+fn test_ring() {
+    // Add one to every input and pass it on, until the value reaches 10.
+    let program = vec![
+        3, 20, 1001, 20, 1, 20, 4, 20, 1007, 20, 10, 21, 1005, 21, 0, 99, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut topology = Topology::new();
+    let first = topology.node(Machine::new(program.clone()), vec![0]);
+    let second = topology.node(Machine::new(program), vec![]);
+    topology.edge(first, second);
+    topology.edge(second, first);
+    let report = topology.run();
+
+    assert!(report.finished);
+    assert_eq!(report[first].outputs, vec![1, 3, 5, 7, 9, 11]);
+    assert_eq!(report[second].outputs, vec![2, 4, 6, 8, 10]);
+    assert_eq!(report[second].unread, vec![11]);
+}
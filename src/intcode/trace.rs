@@ -0,0 +1,132 @@
+//! # trace - a line per executed instruction
+//!
+//! The [`TextTracer`] writes a trace like this:
+//!
+//! ```text
+//! 0000: ARB  #3                  ; ops 3; rb 0 -> 3
+//! 0002: ADD  #4, #2, rb+6        ; ops 4, 2; [9] 0 -> 6
+//! 0006: JNZ  [9], #10            ; ops 6, 10
+//! 0010: HLT
+//! ```
+//!
+//! Every line holds the values read from the parameters, followed by the cell
+//! written to and any change of the relative base. Parameters which aren't
+//! read are written as `_`.
+use super::{Trace, Tracer};
+use std::fmt::{self, Write as _};
+use std::io;
+
+/// A tracer writing a line per instruction to any writer.
+///
+/// The first error the writer returns stops the trace; it can be retrieved
+/// with [`TextTracer::into_inner`].
+#[derive(Debug)]
+pub struct TextTracer<W> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> TextTracer<W> {
+    pub fn new(out: W) -> Self {
+        TextTracer { out, error: None }
+    }
+
+    /// Get the writer back, or the first error it returned.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.out),
+        }
+    }
+}
+
+/// Format a trace the way [`TextTracer`] writes it, without a line break.
+pub fn format<C: fmt::Display>(trace: &Trace<C>) -> String {
+    let mut details = Vec::new();
+
+    let used = trace
+        .operands
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |last| last + 1);
+    if used > 0 {
+        let operands = trace.operands[..used]
+            .iter()
+            .map(|op| {
+                op.as_ref()
+                    .map_or_else(|| "_".to_owned(), |v| v.to_string())
+            })
+            .collect::<Vec<_>>();
+        details.push(format!("ops {}", operands.join(", ")));
+    }
+    if let Some(write) = &trace.write {
+        details.push(format!(
+            "[{}] {} -> {}",
+            write.address, write.old, write.new
+        ));
+    }
+    if let Some(new) = trace.new_relative_base {
+        details.push(format!("rb {} -> {}", trace.relative_base, new));
+    }
+
+    let mut line = format!("{:04}: ", trace.pc);
+    if details.is_empty() {
+        let _ = write!(line, "{}", trace.instr);
+    } else {
+        let _ = write!(
+            line,
+            "{:<24} ; {}",
+            trace.instr.to_string(),
+            details.join("; ")
+        );
+    }
+    line
+}
+
+impl<C: fmt::Display, W: io::Write> Tracer<C> for TextTracer<W> {
+    fn trace(&mut self, trace: &Trace<C>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Err(e) = writeln!(self.out, "{}", format(trace)) {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[test]
+fn test_text_tracer() {
+    let mut tracer = TextTracer::new(Vec::new());
+    let mut machine = super::Machine::new(vec![109, 3, 21101, 4, 2, 6, 1005, 9, 10, 0, 99]);
+    let res = machine.run_traced(&mut super::NoIoBusImpl::default(), &mut tracer);
+    assert!(res.has_halted());
+
+    let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+    assert_eq!(
+        trace,
+        "\
+0000: ARB  #3                  ; ops 3; rb 0 -> 3
+0002: ADD  #4, #2, rb+6        ; ops 4, 2; [9] 0 -> 6
+0006: JNZ  [9], #10            ; ops 6, 10
+0010: HLT
+"
+    );
+}
+
+#[test]
+fn test_not_taken() {
+    let mut tracer = TextTracer::new(Vec::new());
+    let mut machine = super::Machine::new(vec![1105, 0, 7, 1106, 1, 7, 99, 99]);
+    machine.run_traced(&mut super::NoIoBusImpl::default(), &mut tracer);
+
+    let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+    assert_eq!(
+        trace,
+        "\
+0000: JNZ  #0, #7              ; ops 0
+0003: JZ   #1, #7              ; ops 1
+0006: HLT
+"
+    );
+}
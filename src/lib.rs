@@ -17,6 +17,7 @@ pub mod day9;
 pub mod day10;
 pub mod day11;
 pub mod day12;
+pub mod day23;
 
 aoc_lib! {
     year = 2019